@@ -6,4 +6,16 @@ pub struct Args {
     pub port: u16,
     #[clap(long, default_value = "127.0.0.1", help = "ip to connect to")]
     pub ip: String,
+    #[clap(long, default_value = "", help = "user to log in as")]
+    pub user: String,
+    #[clap(long, default_value = "", help = "password to log in with")]
+    pub password: String,
+    #[clap(long, default_value = "world", help = "world to join")]
+    pub world: String,
+    #[clap(
+        long,
+        default_value = "raws",
+        help = "directory with the block and item raws, must match the server's"
+    )]
+    pub raws: String,
 }
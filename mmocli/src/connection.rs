@@ -1,23 +1,201 @@
-use futures::{future, pin_mut, StreamExt};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use futures::{SinkExt, StreamExt};
+use mmolib::codec::Codec;
+use mmolib::protocol;
+use mmolib::server_request_type::ServerRequestType;
+use mmolib::server_response_type::{ServerMessage, ServerResponseType};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/**
+ * Who to log in as and which world to join.
+ */
+#[derive(Clone)]
+pub struct Login {
+    pub user: String,
+    pub password: String,
+    pub world_name: String,
+}
+
+/**
+ * What the network task reports back to the app.
+ */
+pub enum ConnectionEvent {
+    Joined,
+    Push(ServerResponseType),
+    Closed(String),
+}
+
+/**
+ * The app's end of a connection. Dropping it closes the socket.
+ */
 pub struct Connection {
-    ws: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    requests: mpsc::UnboundedSender<ServerRequestType>,
+    events: mpsc::UnboundedReceiver<ConnectionEvent>,
 }
+
 impl Connection {
-    pub async fn new(url: &str) -> Result<Self, tokio_tungstenite::tungstenite::Error> {
-        Ok(Self {
-            ws: Some(tokio_tungstenite::connect_async(url).await?.0),
-        })
+    /**
+     * Connects, logs in and joins the world on the given runtime. Progress arrives through poll.
+     */
+    pub fn start(runtime: &tokio::runtime::Handle, url: &str, login: Login) -> Self {
+        let (requests, request_queue) = mpsc::unbounded_channel();
+        let (event_queue, events) = mpsc::unbounded_channel();
+        runtime.spawn(run(url.to_owned(), login, request_queue, event_queue));
+        Self {
+            requests: requests,
+            events: events,
+        }
+    }
+    pub fn send(&self, request: ServerRequestType) {
+        //a closed connection is reported through poll
+        self.requests.send(request).ok();
+    }
+    pub fn poll(&mut self) -> Option<ConnectionEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+async fn run(
+    url: String,
+    login: Login,
+    requests: mpsc::UnboundedReceiver<ServerRequestType>,
+    events: mpsc::UnboundedSender<ConnectionEvent>,
+) {
+    let reason = match session(&url, &login, requests, &events).await {
+        Ok(()) => "Disconnected".to_owned(),
+        Err(reason) => reason,
+    };
+    events.send(ConnectionEvent::Closed(reason)).ok();
+}
+
+/**
+ * Sends a request as json, with the request_id and session token next to the request type.
+ */
+async fn send_request(
+    socket: &mut Socket,
+    request: &ServerRequestType,
+    request_id: Option<u64>,
+    session_token: Option<&str>,
+) -> Result<(), String> {
+    let mut value = serde_json::to_value(request).map_err(|e| e.to_string())?;
+    if let Value::Object(fields) = &mut value {
+        if let Some(request_id) = request_id {
+            fields.insert("request_id".to_owned(), request_id.into());
+        }
+        if let Some(session_token) = session_token {
+            fields.insert("session_token".to_owned(), session_token.into());
+        }
+    }
+    socket
+        .send(Message::Text(value.to_string()))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn next_message(socket: &mut Socket) -> Result<ServerMessage, String> {
+    loop {
+        let bytes = match socket.next().await {
+            Some(Ok(Message::Text(txt))) => txt.into_bytes(),
+            Some(Ok(Message::Binary(bytes))) => bytes,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.to_string()),
+            None => return Err("Server closed the connection".to_owned()),
+        };
+        return Codec::Json.decode(&bytes).map_err(|e| e.to_string());
+    }
+}
+
+/**
+ * Sends a request and waits for its reply. Pushes that arrive in the meantime are passed on.
+ */
+async fn call(
+    socket: &mut Socket,
+    events: &mpsc::UnboundedSender<ConnectionEvent>,
+    request_id: u64,
+    request: &ServerRequestType,
+    session_token: Option<&str>,
+) -> Result<ServerResponseType, String> {
+    send_request(socket, request, Some(request_id), session_token).await?;
+    loop {
+        match next_message(socket).await? {
+            ServerMessage::Reply {
+                request_id: Some(id),
+                response,
+            } if id == request_id => return Ok(response),
+            ServerMessage::Reply { .. } => {}
+            ServerMessage::Push { response } => {
+                events.send(ConnectionEvent::Push(response)).ok();
+            }
+        }
+    }
+}
+
+async fn session(
+    url: &str,
+    login: &Login,
+    mut requests: mpsc::UnboundedReceiver<ServerRequestType>,
+    events: &mpsc::UnboundedSender<ConnectionEvent>,
+) -> Result<(), String> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let hello = ServerRequestType::Hello {
+        protocol_version: protocol::PROTOCOL_VERSION,
+        codec: Codec::Json,
+        features: vec![protocol::FEATURE_DELTA_COMPRESSION.to_owned()],
+    };
+    match call(&mut socket, events, 0, &hello, None).await? {
+        ServerResponseType::Welcome { .. } => {}
+        _ => return Err("Server rejected the handshake".to_owned()),
     }
-    pub fn new_dont_start() -> Self {
-        Self { ws: None }
+    let credentials = ServerRequestType::Login {
+        user: login.user.clone(),
+        password: login.password.clone(),
+    };
+    let session_token = match call(&mut socket, events, 1, &credentials, None).await? {
+        ServerResponseType::AuthSuccess { session_token, .. } => session_token,
+        _ => return Err("Login failed".to_owned()),
+    };
+    let join = ServerRequestType::Join {
+        world_name: login.world_name.clone(),
+    };
+    match call(&mut socket, events, 2, &join, Some(&session_token)).await? {
+        ServerResponseType::Ok {} => {}
+        _ => return Err(format!("Could not join {}", login.world_name)),
     }
-    pub fn get(&mut self) -> Option<&mut WebSocketStream<MaybeTlsStream<TcpStream>>> {
-        self.ws.as_mut()
+    events.send(ConnectionEvent::Joined).ok();
+    let spawn = ServerRequestType::Spawn {
+        world_name: login.world_name.clone(),
+        player_parameters: String::new(),
+    };
+    send_request(&mut socket, &spawn, None, Some(&session_token)).await?;
+    loop {
+        tokio::select! {
+            request = requests.recv() => match request {
+                Some(request) => {
+                    send_request(&mut socket, &request, None, Some(&session_token)).await?
+                }
+                //the app dropped its end
+                None => {
+                    socket.close(None).await.ok();
+                    return Ok(());
+                }
+            },
+            message = next_message(&mut socket) => match message? {
+                ServerMessage::Push { response } => {
+                    events.send(ConnectionEvent::Push(response)).ok();
+                }
+                ServerMessage::Reply { response, .. } => {
+                    if let ServerResponseType::Error { code, detail } = response {
+                        log::warn!("Server refused a request: {:?} {:?}", code, detail);
+                    }
+                }
+            },
+        }
     }
 }
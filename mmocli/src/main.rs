@@ -10,12 +10,29 @@ mod args;
 mod connection;
 mod game_state;
 mod help_menu;
+mod main_game;
 mod main_menu;
+mod prediction;
 #[tokio::main]
 async fn main() {
     let args = args::Args::parse();
     let mut wind = dialog::input(0, 0, "Enter IP Address", "mo");
     let gs = game_state::GameState::MainMenu;
+    let raws = mmolib::raws::RawTree::new(&args.raws);
+    let settings = main_game::ServerSettings {
+        url: format!("ws://{}:{}", args.ip, args.port),
+        login: connection::Login {
+            user: args.user.clone(),
+            password: args.password.clone(),
+            world_name: args.world.clone(),
+        },
+        registry: std::sync::Arc::new(
+            mmolib::registry::RegistryBuilder::new()
+                .load_block_raws(&["block"], &raws)
+                .load_item_raws(&["item"], &raws)
+                .build(),
+        ),
+    };
     bevy::app::App::new()
         .insert_resource(WindowDescriptor {
             title: "Mordax".to_owned(),
@@ -23,9 +40,11 @@ async fn main() {
             ..default()
         })
         .add_state(gs)
-        //.insert_resource(connection)
+        .insert_resource(settings)
+        .insert_resource(tokio::runtime::Handle::current())
         .add_plugins(DefaultPlugins)
         .add_plugin(main_menu::MainMenuPlugin {})
         .add_plugin(help_menu::HelpMenuPlugin {})
+        .add_plugin(main_game::MainGamePlugin {})
         .run();
 }
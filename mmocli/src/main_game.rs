@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::connection::{Connection, ConnectionEvent, Login};
+use crate::game_state::GameState;
+use crate::prediction::PredictedMovement;
+use bevy::prelude::*;
+use bevy::winit::WinitSettings;
+use mmolib::active_effects::ActiveEffects;
+use mmolib::chunk;
+use mmolib::chunk_map::ChunkMap;
+use mmolib::component::{get_type_id, ComponentTypeId};
+use mmolib::delta;
+use mmolib::entity_id::EntityId;
+use mmolib::movement_event::MovementRules;
+use mmolib::player::Player;
+use mmolib::position::Position;
+use mmolib::registry::Registry;
+use mmolib::server_request_type::{Direction, PlayerActionType, ServerRequestType};
+use mmolib::server_response_type::{ComponentUpdateType, ServerResponseType};
+use mmolib::terrain::Swimmer;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/**
+ * Where to connect and what to join once the game starts.
+ */
+pub struct ServerSettings {
+    pub url: String,
+    pub login: Login,
+    pub registry: Arc<Registry>,
+}
+
+/**
+ * A push received from the server, forwarded into the app by the connection.
+ */
+pub struct ServerPush(pub ServerResponseType);
+
+/**
+ * Requests the game wants sent, drained by the connection.
+ */
+#[derive(Default)]
+pub struct OutgoingRequests {
+    requests: Vec<ServerRequestType>,
+}
+
+impl OutgoingRequests {
+    pub fn push(&mut self, request: ServerRequestType) {
+        self.requests.push(request);
+    }
+    pub fn drain(&mut self) -> Vec<ServerRequestType> {
+        std::mem::take(&mut self.requests)
+    }
+}
+
+/**
+ * What the client knows about the world it joined. Inserted once the server has accepted the Join.
+ */
+pub struct LocalWorld {
+    world_name: String,
+    username: String,
+    registry: Arc<Registry>,
+    chunk_map: ChunkMap,
+    player: Option<EntityId>,
+    //the last full value of each of the player's components, patched updates are applied on top
+    player_components: HashMap<ComponentTypeId, Value>,
+}
+
+impl LocalWorld {
+    pub fn new(world_name: &str, username: &str, registry: Arc<Registry>) -> Self {
+        Self {
+            world_name: world_name.to_owned(),
+            username: username.to_owned(),
+            registry: registry,
+            chunk_map: ChunkMap::new(),
+            player: None,
+            player_components: HashMap::new(),
+        }
+    }
+    fn player_component<T: DeserializeOwned + 'static>(&self) -> Option<T> {
+        self.player_components
+            .get(&get_type_id::<T>())
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+    pub fn get_player_position(&self) -> Option<chunk::Position> {
+        self.player_component::<Position>().map(|p| p.pos)
+    }
+    /**
+     * The rules the server moves the player with, given what the client has been sent.
+     */
+    pub fn movement_rules(&self) -> MovementRules<'_> {
+        MovementRules::new(
+            &self.chunk_map,
+            &self.registry,
+            self.player_component::<Swimmer>().as_ref(),
+            self.player_component::<ActiveEffects>().as_ref(),
+        )
+    }
+    fn apply_ticked(&mut self, ticked: &ServerResponseType) {
+        let (component_updates, block_updates, removed_entities, loaded_chunks, unloaded_chunks) =
+            match ticked {
+                ServerResponseType::Ticked {
+                    component_updates,
+                    block_updates,
+                    removed_entities,
+                    loaded_chunks,
+                    unloaded_chunks,
+                    ..
+                } => (
+                    component_updates,
+                    block_updates,
+                    removed_entities,
+                    loaded_chunks,
+                    unloaded_chunks,
+                ),
+                _ => return,
+            };
+        for payload in loaded_chunks {
+            self.chunk_map.add(payload.chunk_id, payload.chunk.clone());
+        }
+        for chunk_id in unloaded_chunks {
+            self.chunk_map.remove(*chunk_id);
+        }
+        for update in block_updates {
+            self.chunk_map
+                .set_block(update.block_pos, update.block_type_id);
+        }
+        self.chunk_map.clear_trackers();
+        if let Some(player) = self.player {
+            if removed_entities.contains(&player) {
+                self.player = None;
+                self.player_components.clear();
+            }
+        }
+        //the player entity is the one whose Player component has our username
+        if self.player.is_none() {
+            self.player = component_updates
+                .iter()
+                .filter(|update| update.get_component_type_id() == get_type_id::<Player>())
+                .find(|update| match update.get_component_update_info() {
+                    ComponentUpdateType::Added { packet }
+                    | ComponentUpdateType::Changed { packet } => {
+                        serde_json::from_value::<Player>(packet.clone())
+                            .map_or(false, |player| player.username == self.username)
+                    }
+                    _ => false,
+                })
+                .map(|update| update.get_entity_id());
+        }
+        for update in component_updates {
+            if Some(update.get_entity_id()) != self.player {
+                continue;
+            }
+            let type_id = update.get_component_type_id();
+            match update.get_component_update_info() {
+                ComponentUpdateType::Added { packet } | ComponentUpdateType::Changed { packet } => {
+                    self.player_components.insert(type_id, packet.clone());
+                }
+                ComponentUpdateType::Patched { fields } => {
+                    if let Some(base) = self.player_components.get_mut(&type_id) {
+                        delta::apply_fields(base, fields);
+                    }
+                }
+                ComponentUpdateType::Removed => {
+                    self.player_components.remove(&type_id);
+                }
+            }
+        }
+    }
+}
+
+pub struct MainGamePlugin {}
+impl Plugin for MainGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ServerPush>()
+            .init_resource::<OutgoingRequests>()
+            .add_system_set(SystemSet::on_enter(GameState::MainGame).with_system(connect))
+            .add_system_set(
+                SystemSet::on_update(GameState::MainGame)
+                    .with_system(receive_system)
+                    .with_system(ticked_system.after(receive_system))
+                    .with_system(movement_input_system.after(ticked_system))
+                    .with_system(send_system.after(movement_input_system)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::MainGame).with_system(disconnect));
+    }
+}
+
+fn connect(
+    mut commands: Commands,
+    settings: Res<ServerSettings>,
+    runtime: Res<tokio::runtime::Handle>,
+) {
+    info!("Connecting to {}", settings.url);
+    commands.insert_resource(Connection::start(
+        &runtime,
+        &settings.url,
+        settings.login.clone(),
+    ));
+    //ticks arrive without any input, so keep updating
+    commands.insert_resource(WinitSettings::game());
+}
+
+fn disconnect(mut commands: Commands, players: Query<Entity, With<PredictedMovement>>) {
+    //dropping the connection closes the socket
+    commands.remove_resource::<Connection>();
+    commands.remove_resource::<LocalWorld>();
+    commands.insert_resource(WinitSettings::desktop_app());
+    for entity in players.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/**
+ * Passes everything the connection received on to the rest of the app.
+ */
+fn receive_system(
+    mut commands: Commands,
+    connection: Option<ResMut<Connection>>,
+    settings: Res<ServerSettings>,
+    mut pushes: EventWriter<ServerPush>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let mut connection = match connection {
+        Some(connection) => connection,
+        None => return,
+    };
+    while let Some(event) = connection.poll() {
+        match event {
+            ConnectionEvent::Joined => {
+                commands.insert_resource(LocalWorld::new(
+                    &settings.login.world_name,
+                    &settings.login.user,
+                    settings.registry.clone(),
+                ));
+            }
+            ConnectionEvent::Push(response) => pushes.send(ServerPush(response)),
+            ConnectionEvent::Closed(reason) => {
+                warn!("Lost connection to the server: {}", reason);
+                state
+                    .set(GameState::MainMenu)
+                    .expect("Couldn't change the game state");
+                return;
+            }
+        }
+    }
+}
+
+fn send_system(connection: Option<Res<Connection>>, mut outgoing: ResMut<OutgoingRequests>) {
+    let requests = outgoing.drain();
+    if let Some(connection) = connection {
+        for request in requests {
+            connection.send(request);
+        }
+    }
+}
+
+fn key_direction(keys: &Input<KeyCode>) -> Option<Direction> {
+    [
+        (KeyCode::W, Direction::North),
+        (KeyCode::Up, Direction::North),
+        (KeyCode::D, Direction::East),
+        (KeyCode::Right, Direction::East),
+        (KeyCode::S, Direction::South),
+        (KeyCode::Down, Direction::South),
+        (KeyCode::A, Direction::West),
+        (KeyCode::Left, Direction::West),
+        (KeyCode::E, Direction::Northeast),
+        (KeyCode::C, Direction::Southeast),
+        (KeyCode::Z, Direction::Southwest),
+        (KeyCode::Q, Direction::Northwest),
+    ]
+    .into_iter()
+    .find(|(key, _)| keys.just_pressed(*key))
+    .map(|(_, direction)| direction)
+}
+
+/**
+ * Sends a Move for every key press and moves the player right away instead of waiting for the server.
+ */
+fn movement_input_system(
+    keys: Res<Input<KeyCode>>,
+    world: Option<Res<LocalWorld>>,
+    mut players: Query<&mut PredictedMovement>,
+    mut outgoing: ResMut<OutgoingRequests>,
+) {
+    let (world, direction) = match (world, key_direction(&keys)) {
+        (Some(world), Some(direction)) => (world, direction),
+        _ => return,
+    };
+    for mut predicted in players.iter_mut() {
+        let sequence = predicted.push_input(direction, &world.movement_rules());
+        outgoing.push(ServerRequestType::PlayerAction {
+            world_name: world.world_name.clone(),
+            action: PlayerActionType::Move(direction),
+            input_sequence: Some(sequence),
+        });
+    }
+}
+
+/**
+ * Applies every Ticked to the local world and reconciles the predicted position with the server's.
+ */
+fn ticked_system(
+    mut commands: Commands,
+    mut pushes: EventReader<ServerPush>,
+    world: Option<ResMut<LocalWorld>>,
+    mut players: Query<(Entity, &mut PredictedMovement)>,
+) {
+    let mut world = match world {
+        Some(world) => world,
+        None => return,
+    };
+    //only the newest tick matters, reconciling replays everything the server hasn't acknowledged
+    let mut last_processed_input = None;
+    let mut ticked = false;
+    for push in pushes.iter() {
+        if let ServerResponseType::Ticked {
+            last_processed_input: ack,
+            ..
+        } = &push.0
+        {
+            world.apply_ticked(&push.0);
+            last_processed_input = *ack;
+            ticked = true;
+        }
+    }
+    if !ticked {
+        return;
+    }
+    match (world.get_player_position(), players.get_single_mut()) {
+        (Some(authoritative), Ok((_, mut predicted))) => {
+            predicted.reconcile(authoritative, last_processed_input, &world.movement_rules());
+        }
+        //prediction starts from the first position the server sends
+        (Some(authoritative), Err(_)) => {
+            commands
+                .spawn()
+                .insert(PredictedMovement::new(authoritative));
+        }
+        (None, Ok((entity, _))) => {
+            commands.entity(entity).despawn();
+        }
+        (None, Err(_)) => {}
+    }
+}
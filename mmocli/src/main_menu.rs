@@ -84,7 +84,11 @@ fn button_system(
             *image = UiImage(asset_server.load(image_path));
         } else if *interaction == Interaction::Clicked {
             match action {
-                MainMenuButtonActions::Connect => {}
+                MainMenuButtonActions::Connect => {
+                    state
+                        .set(game_state::GameState::MainGame)
+                        .expect("Couldn't change the game state");
+                }
                 MainMenuButtonActions::Quit => {
                     exit.send(AppExit);
                 }
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use mmolib::chunk;
+use mmolib::movement_event::MovementRules;
+use mmolib::server_request_type::Direction;
+
+/**
+ * Moves the local player as soon as a Move is sent instead of waiting for the server, then replays the moves the server hasn't processed yet over every authoritative position.
+ * Moves are simulated with the server's movement rules, so a move into a wall or an unloaded chunk is predicted to fail the same way it will on the server.
+ */
+#[derive(Component)]
+pub struct PredictedMovement {
+    next_sequence: u64,
+    unacknowledged: VecDeque<(u64, Direction)>,
+    predicted: chunk::Position,
+}
+
+impl PredictedMovement {
+    /**
+     * Starts predicting from the first authoritative position the server sent for the player.
+     */
+    pub fn new(authoritative: chunk::Position) -> Self {
+        Self {
+            next_sequence: 0,
+            unacknowledged: VecDeque::new(),
+            predicted: authoritative,
+        }
+    }
+    /**
+     * Applies a move locally and returns the input_sequence to send with its PlayerAction.
+     */
+    pub fn push_input(&mut self, direction: Direction, rules: &MovementRules) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.unacknowledged.push_back((sequence, direction));
+        self.predicted = rules.destination(self.predicted, direction);
+        sequence
+    }
+    /**
+     * Rolls back to the server's position and reapplies every input after the acknowledged one. Call this on every Ticked with the latest authoritative Position.
+     */
+    pub fn reconcile(
+        &mut self,
+        authoritative: chunk::Position,
+        last_processed_input: Option<u64>,
+        rules: &MovementRules,
+    ) -> chunk::Position {
+        if let Some(ack) = last_processed_input {
+            while matches!(self.unacknowledged.front(), Some((sequence, _)) if *sequence <= ack) {
+                self.unacknowledged.pop_front();
+            }
+        }
+        self.predicted = self
+            .unacknowledged
+            .iter()
+            .fold(authoritative, |p, (_, direction)| {
+                rules.destination(p, *direction)
+            });
+        self.predicted
+    }
+    pub fn get_predicted_position(&self) -> chunk::Position {
+        self.predicted
+    }
+}
+
+#[test]
+fn test_reconcile() {
+    use mmolib::chunk_map::ChunkMap;
+    let registry = mmolib::registry::RegistryBuilder::new()
+        .load_block_raws(
+            &["block"],
+            &mmolib::raws::RawTree::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../raws")),
+        )
+        .build();
+    let stone = registry.get_block_type("stonefloor").unwrap().get_id();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.add(
+        chunk::chunk_id_from_position((0, 0)),
+        chunk::Chunk::new_from_array([[stone; chunk::CHUNK_SIZE]; chunk::CHUNK_SIZE]),
+    );
+    let rules = MovementRules::new(&chunk_map, &registry, None, None);
+    let mut predicted = PredictedMovement::new((5, 5));
+    let first = predicted.push_input(Direction::East, &rules);
+    let second = predicted.push_input(Direction::East, &rules);
+    predicted.push_input(Direction::South, &rules);
+    assert_eq!(predicted.get_predicted_position(), (7, 6));
+    //the server moved the player somewhere else after the first move, the other two are replayed from there
+    assert_eq!(predicted.reconcile((6, 8), Some(first), &rules), (7, 9));
+    assert_eq!(predicted.unacknowledged.len(), 2);
+    //acknowledged inputs are never replayed again
+    assert_eq!(predicted.reconcile((7, 9), Some(second), &rules), (7, 10));
+    assert_eq!(predicted.unacknowledged.len(), 1);
+}
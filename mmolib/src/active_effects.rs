@@ -0,0 +1,161 @@
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+use bevy_reflect::ReflectDeserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::effect::Effect;
+use crate::health::Health;
+use crate::terrain::TerrainEvent;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ActiveEffect {
+    pub effect: Effect,
+    pub remaining_ticks: u32,
+    pub stacks: u32,
+}
+
+/**
+ * The status effects currently applied to an entity.
+ */
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct ActiveEffects {
+    effects: Vec<ActiveEffect>,
+}
+
+impl ActiveEffects {
+    pub fn new() -> Self {
+        Self {
+            effects: Vec::new(),
+        }
+    }
+    /**
+     * Applies an effect for a duration. Reapplying an effect that is already active refreshes its duration and adds a stack up to the effect's cap.
+     */
+    pub fn apply(&mut self, effect: Effect, duration: u32) {
+        match self.effects.iter_mut().find(|x| x.effect == effect) {
+            Some(active) => {
+                active.remaining_ticks = active.remaining_ticks.max(duration);
+                active.stacks = (active.stacks + 1).min(effect.max_stacks());
+            }
+            None => {
+                self.effects.push(ActiveEffect {
+                    effect: effect,
+                    remaining_ticks: duration,
+                    stacks: 1,
+                });
+            }
+        }
+    }
+    pub fn remove(&mut self, effect: Effect) {
+        self.effects.retain(|x| x.effect != effect);
+    }
+    pub fn get(&self, effect: Effect) -> Option<&ActiveEffect> {
+        self.effects.iter().find(|x| x.effect == effect)
+    }
+    pub fn has(&self, effect: Effect) -> bool {
+        self.get(effect).is_some()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+    pub fn effects(&self) -> &Vec<ActiveEffect> {
+        &self.effects
+    }
+    /**
+     * Damage the active effects deal this tick.
+     */
+    pub fn damage_per_tick(&self) -> u32 {
+        self.effects
+            .iter()
+            .map(|x| x.effect.damage_per_tick() * x.stacks)
+            .sum()
+    }
+    /**
+     * How many tiles a single move action covers.
+     */
+    pub fn movement_steps(&self) -> u32 {
+        if self.has(Effect::Haste) {
+            2
+        } else {
+            1
+        }
+    }
+    /**
+     * Multiplier applied to outgoing attack damage, 50% per stack of strength.
+     */
+    pub fn attack_multiplier(&self) -> f32 {
+        match self.get(Effect::Strength) {
+            Some(strength) => 1.0 + 0.5 * strength.stacks as f32,
+            None => 1.0,
+        }
+    }
+    fn tick(&mut self) {
+        for active in self.effects.iter_mut() {
+            active.remaining_ticks = active.remaining_ticks.saturating_sub(1);
+        }
+        self.effects.retain(|x| x.remaining_ticks > 0);
+    }
+}
+
+/**
+ * Deals damage over time and counts down the effect durations.
+ */
+pub fn active_effects_tick_system(mut query: Query<(&mut ActiveEffects, Option<&mut Health>)>) {
+    for (mut effects, health) in query.iter_mut() {
+        //only mutably deref when there is something to do, so idle components aren't marked changed
+        if effects.is_empty() {
+            continue;
+        }
+        if let Some(mut health) = health {
+            let damage = effects.damage_per_tick();
+            if damage > 0 && !health.is_dead() {
+                health.damage(damage);
+            }
+        }
+        effects.tick();
+    }
+}
+
+/**
+ * Applies the effect of effect blocks to entities that step on them.
+ */
+pub fn apply_terrain_effects(
+    mut terrain_events: EventReader<TerrainEvent>,
+    mut query: Query<Option<&mut ActiveEffects>>,
+    mut commands: Commands,
+) {
+    for event in terrain_events.iter() {
+        if let TerrainEvent::SteppedOnEffect { entity, effect } = event {
+            match query.get_mut(*entity) {
+                Ok(Some(mut effects)) => {
+                    effects.apply(*effect, effect.default_duration());
+                }
+                Ok(None) => {
+                    let mut effects = ActiveEffects::new();
+                    effects.apply(*effect, effect.default_duration());
+                    commands.entity(*entity).insert(effects);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_effect_stacking() {
+    let mut effects = ActiveEffects::new();
+    for _ in 0..10 {
+        effects.apply(Effect::Poison, 5);
+    }
+    effects.apply(Effect::Fire, 2);
+    effects.apply(Effect::Fire, 1);
+    assert_eq!(effects.get(Effect::Poison).unwrap().stacks, 5);
+    assert_eq!(effects.get(Effect::Fire).unwrap().stacks, 1);
+    assert_eq!(effects.get(Effect::Fire).unwrap().remaining_ticks, 2);
+    assert_eq!(effects.damage_per_tick(), 5 + 3);
+    effects.tick();
+    effects.tick();
+    assert!(!effects.has(Effect::Fire));
+    assert!(effects.has(Effect::Poison));
+}
@@ -28,6 +28,17 @@ impl Chunk {
     pub fn new_from_array(blocks: [[block_type::BlockTypeId; CHUNK_SIZE]; CHUNK_SIZE]) -> Self {
         Self { blocks: blocks }
     }
+    /**
+     * Gets the block at a world position, which is converted to a position relative to this chunk.
+     */
+    pub fn get_block(&self, position: Position) -> block_type::BlockTypeId {
+        let (x, y) = convert_to_chunk_relative_position(position);
+        self.blocks[x as usize][y as usize]
+    }
+    pub fn set_block(&mut self, position: Position, block: block_type::BlockTypeId) {
+        let (x, y) = convert_to_chunk_relative_position(position);
+        self.blocks[x as usize][y as usize] = block;
+    }
 }
 #[derive(Eq, Hash, PartialEq, Copy, Clone, Deserialize, Serialize, Debug)]
 pub struct ChunkId(u64);
@@ -67,6 +78,15 @@ pub fn distance_between_position(a: Position, b: Position) -> f32 {
     ((x1 - x2) as f32).hypot((y1 - y2) as f32)
 }
 
+/**
+ * Number of single tile moves (diagonals included) between two positions.
+ */
+pub fn tile_distance_between_position(a: Position, b: Position) -> u32 {
+    let (x1, y1) = a;
+    let (x2, y2) = b;
+    x1.abs_diff(x2).max(y1.abs_diff(y2))
+}
+
 #[test]
 fn test_chunks() {
     let p: Position = (32, 64);
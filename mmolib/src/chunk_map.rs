@@ -1,10 +1,12 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::chunk::{Chunk, ChunkId, Position};
+use crate::block_type::BlockTypeId;
+use crate::chunk::{self, Chunk, ChunkId, Position};
 
 pub struct ChunkMap {
     chunks: HashMap<ChunkId, Chunk>,
     change_tracking: HashSet<ChunkId>,
+    block_changes: HashMap<ChunkId, HashMap<Position, BlockTypeId>>,
 }
 
 impl ChunkMap {
@@ -12,6 +14,7 @@ impl ChunkMap {
         Self {
             chunks: HashMap::new(),
             change_tracking: HashSet::new(),
+            block_changes: HashMap::new(),
         }
     }
     pub fn add(&mut self, chunk_id: ChunkId, chunk: Chunk) {
@@ -24,6 +27,38 @@ impl ChunkMap {
     pub fn get(&self, chunk_id: ChunkId) -> Option<&Chunk> {
         self.chunks.get(&chunk_id)
     }
+    pub fn get_block(&self, position: Position) -> Option<BlockTypeId> {
+        self.chunks
+            .get(&chunk::chunk_id_from_position(position))
+            .map(|chunk| chunk.get_block(position))
+    }
+    /**
+     * Sets a block in a loaded chunk and records the change for saving and for clients. Returns false if the chunk isn't loaded.
+     */
+    pub fn set_block(&mut self, position: Position, block: BlockTypeId) -> bool {
+        let chunk_id = chunk::chunk_id_from_position(position);
+        match self.chunks.get_mut(&chunk_id) {
+            Some(chunk) => {
+                chunk.set_block(position, block);
+                self.change_tracking.insert(chunk_id);
+                self.block_changes
+                    .entry(chunk_id)
+                    .or_default()
+                    .insert(position, block);
+                true
+            }
+            None => false,
+        }
+    }
+    /**
+     * The blocks set in a chunk since the trackers were last cleared.
+     */
+    pub fn get_block_changes(&self, chunk_id: ChunkId) -> Vec<(Position, BlockTypeId)> {
+        match self.block_changes.get(&chunk_id) {
+            Some(changes) => changes.iter().map(|(p, b)| (*p, *b)).collect(),
+            None => Vec::new(),
+        }
+    }
     pub fn remove(&mut self, chunk_id: ChunkId) -> Option<Chunk> {
         self.chunks.remove(&chunk_id)
     }
@@ -35,5 +70,24 @@ impl ChunkMap {
     }
     pub fn clear_trackers(&mut self) {
         self.change_tracking.clear();
+        self.block_changes.clear();
     }
 }
+
+#[test]
+fn test_set_block() {
+    let mut map = ChunkMap::new();
+    let chunk_id = chunk::chunk_id_from_position((40, 8));
+    map.add(
+        chunk_id,
+        Chunk::new_from_array([[0; chunk::CHUNK_SIZE]; chunk::CHUNK_SIZE]),
+    );
+    map.clear_trackers();
+    assert!(map.set_block((40, 8), 7));
+    assert_eq!(map.get_block((40, 8)), Some(7));
+    assert!(map.is_chunk_changed(chunk_id));
+    assert_eq!(map.get_block_changes(chunk_id), vec![((40, 8), 7)]);
+    assert!(!map.set_block((4000, 8), 7));
+    map.clear_trackers();
+    assert!(map.get_block_changes(chunk_id).is_empty());
+}
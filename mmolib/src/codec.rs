@@ -0,0 +1,101 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/**
+ * How messages are encoded on the wire, negotiated per connection. Json is sent as text frames and Cbor as binary frames.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    Cbor,
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Json(serde_json::Error),
+    Cbor(serde_cbor::Error),
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CodecError::Json(e) => write!(f, "{}", e),
+            CodecError::Cbor(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Json
+    }
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Codec::Json => serde_json::to_vec(value).map_err(CodecError::Json),
+            Codec::Cbor => serde_cbor::to_vec(value).map_err(CodecError::Cbor),
+        }
+    }
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Codec::Json => serde_json::from_slice(bytes).map_err(CodecError::Json),
+            Codec::Cbor => serde_cbor::from_slice(bytes).map_err(CodecError::Cbor),
+        }
+    }
+    pub fn is_binary(&self) -> bool {
+        *self == Codec::Cbor
+    }
+}
+
+#[test]
+fn test_cbor_ticks_are_smaller() {
+    use crate::component::get_type_id;
+    use crate::entity_id::EntityId;
+    use crate::health::Health;
+    use crate::position::Position;
+    use crate::server_response_type::{
+        ComponentUpdate, ComponentUpdateType, ServerMessage, ServerResponseType,
+    };
+
+    let mut updates = Vec::new();
+    for i in 0..50 {
+        let id = EntityId::new_with_number(i * 7919 + 1);
+        updates.push(ComponentUpdate::new(
+            id,
+            get_type_id::<Position>(),
+            ComponentUpdateType::Changed {
+                packet: serde_json::to_value(Position {
+                    pos: (i as u32, 2 * i as u32),
+                    load_with_chunk: true,
+                })
+                .unwrap(),
+            },
+        ));
+        updates.push(ComponentUpdate::new(
+            id,
+            get_type_id::<Health>(),
+            ComponentUpdateType::Changed {
+                packet: serde_json::to_value(Health::new(100)).unwrap(),
+            },
+        ));
+    }
+    let tick = ServerMessage::Push {
+        response: ServerResponseType::Ticked {
+            world_name: "world".to_owned(),
+            tick: 1,
+            last_processed_input: None,
+            component_updates: updates,
+            block_updates: Vec::new(),
+            removed_entities: Vec::new(),
+            loaded_chunks: Vec::new(),
+            unloaded_chunks: Vec::new(),
+        },
+    };
+    let json = Codec::Json.encode(&tick).unwrap();
+    let cbor = Codec::Cbor.encode(&tick).unwrap();
+    assert!(cbor.len() < json.len());
+    let decoded: serde_json::Value = Codec::Cbor.decode(&cbor).unwrap();
+    assert_eq!(decoded, serde_json::to_value(&tick).unwrap());
+}
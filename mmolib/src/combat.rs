@@ -0,0 +1,212 @@
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+use bevy_reflect::ReflectDeserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::active_effects::ActiveEffects;
+use crate::chunk;
+use crate::entity_deletion_list::EntityDeletionList;
+use crate::entity_id::EntityId;
+use crate::health::Health;
+use crate::player::Player;
+use crate::position_map::PositionMap;
+use crate::terrain::TerrainEvent;
+use crate::world_rules::WorldRules;
+
+pub const FALL_DAMAGE: u32 = 20;
+
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct CombatStats {
+    pub attack: u32,
+    pub defense: u32,
+    pub range: u32,
+}
+
+impl CombatStats {
+    pub fn new(attack: u32, defense: u32, range: u32) -> Self {
+        Self {
+            attack: attack,
+            defense: defense,
+            range: range,
+        }
+    }
+}
+
+pub struct AttackEvent {
+    attacker: Entity,
+    target: Entity,
+}
+
+impl AttackEvent {
+    pub fn new(attacker: Entity, target: Entity) -> Self {
+        Self {
+            attacker: attacker,
+            target: target,
+        }
+    }
+    pub fn get_attacker(&self) -> Entity {
+        self.attacker
+    }
+    pub fn get_target(&self) -> Entity {
+        self.target
+    }
+}
+
+/**
+ * Sent once when an entity's health reaches zero.
+ */
+pub struct DeathEvent {
+    pub entity: Entity,
+}
+
+/**
+ * Damage dealt by one attack. Always at least one so that defense can't make a target invulnerable.
+ */
+pub fn calculate_damage(
+    attacker: &CombatStats,
+    defender: Option<&CombatStats>,
+    effects: Option<&ActiveEffects>,
+) -> u32 {
+    let attack = attacker.attack as f32 * effects.map_or(1.0, |x| x.attack_multiplier());
+    let defense = defender.map_or(0, |x| x.defense);
+    (attack as u32).saturating_sub(defense).max(1)
+}
+
+pub fn attack_system(
+    mut attack_events: EventReader<AttackEvent>,
+    position_map: Res<PositionMap>,
+    rules: Res<WorldRules>,
+    attackers: Query<(&CombatStats, Option<&ActiveEffects>, Option<&Player>)>,
+    mut targets: Query<(&mut Health, Option<&CombatStats>, Option<&Player>)>,
+) {
+    for event in attack_events.iter() {
+        if event.attacker == event.target {
+            continue;
+        }
+        let (stats, effects, attacking_player) = match attackers.get(event.attacker) {
+            Ok(attacker) => attacker,
+            Err(_) => continue,
+        };
+        //the dead don't fight back, even when their attack was queued before they died
+        if matches!(targets.get(event.attacker), Ok((health, _, _)) if health.is_dead()) {
+            continue;
+        }
+        match (
+            position_map.get_position(event.attacker),
+            position_map.get_position(event.target),
+        ) {
+            (Some(a), Some(b)) if chunk::tile_distance_between_position(a, b) <= stats.range => {}
+            _ => continue,
+        }
+        let damage = match targets.get(event.target) {
+            Ok((health, defender, target_player)) => {
+                if health.is_dead() {
+                    continue;
+                }
+                if !rules.pvp && attacking_player.is_some() && target_player.is_some() {
+                    continue;
+                }
+                calculate_damage(stats, defender, effects)
+            }
+            Err(_) => continue,
+        };
+        if let Ok((mut health, _, _)) = targets.get_mut(event.target) {
+            health.damage(damage);
+        }
+    }
+}
+
+pub fn fall_damage_system(
+    mut terrain_events: EventReader<TerrainEvent>,
+    mut query: Query<&mut Health>,
+) {
+    for event in terrain_events.iter() {
+        if let TerrainEvent::Fell { entity } = event {
+            //damaging the dead would mark their health changed and kill them again
+            match query.get_mut(*entity) {
+                Ok(mut health) if !health.is_dead() => health.damage(FALL_DAMAGE),
+                _ => {}
+            }
+        }
+    }
+}
+
+/**
+ * Emits death events and schedules dead entities that aren't players for deletion.
+ */
+pub fn death_system(
+    mut death_events: EventWriter<DeathEvent>,
+    mut deletion_list: ResMut<EntityDeletionList>,
+    query: Query<(Entity, &EntityId, &Health, Option<&Player>), Changed<Health>>,
+) {
+    for (entity, entity_id, health, player) in query.iter() {
+        if health.is_dead() {
+            death_events.send(DeathEvent { entity: entity });
+            if player.is_none() {
+                deletion_list.add(*entity_id);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_calculate_damage() {
+    let attacker = CombatStats::new(10, 0, 1);
+    let defender = CombatStats::new(0, 4, 1);
+    assert_eq!(calculate_damage(&attacker, Some(&defender), None), 6);
+    assert_eq!(
+        calculate_damage(&attacker, Some(&CombatStats::new(0, 50, 1)), None),
+        1
+    );
+    let mut effects = ActiveEffects::new();
+    effects.apply(crate::effect::Effect::Strength, 10);
+    assert_eq!(calculate_damage(&attacker, None, Some(&effects)), 15);
+}
+
+#[test]
+fn test_dead_entities_are_left_alone() {
+    let mut world = crate::game_world::GameWorldBuilder::new("combat")
+        .add_event::<AttackEvent>()
+        .add_event::<TerrainEvent>()
+        .add_event::<DeathEvent>()
+        .add_pre_update_system(attack_system)
+        .add_pre_update_system(fall_damage_system)
+        .add_post_update_system(death_system)
+        .build();
+    let target = world
+        .spawn()
+        .insert(Health::new(10))
+        .insert(crate::position::Position {
+            pos: (1, 1),
+            load_with_chunk: false,
+        })
+        .id();
+    let dead = world
+        .spawn()
+        .insert(Health {
+            current: 0,
+            max: 10,
+        })
+        .insert(CombatStats::new(5, 0, 1))
+        .insert(crate::position::Position {
+            pos: (1, 2),
+            load_with_chunk: false,
+        })
+        .id();
+    world.run_between_ticks_scheduler();
+    //the first run reports the entity that spawned dead, drop that event
+    world.run_post_update_scheduler();
+    world.run_event_update_closures();
+    world.run_event_update_closures();
+    world.send_event(AttackEvent::new(dead, target));
+    world.send_event(TerrainEvent::Fell { entity: dead });
+    world.run_pre_update_scheduler();
+    world.run_post_update_scheduler();
+    let w = world.get_world();
+    assert_eq!(w.get::<Health>(target).unwrap().current, 10);
+    let deaths = w
+        .get_resource::<bevy_ecs::event::Events<DeathEvent>>()
+        .unwrap();
+    assert_eq!(deaths.get_reader().iter(deaths).count(), 0);
+}
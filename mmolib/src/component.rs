@@ -14,6 +14,39 @@ pub const fn get_type_id_from_str(s: &str) -> ComponentTypeId {
     ComponentTypeId((hashing::string_hash(s)))
 }
 
+/**
+ * Who a component is sent to and whether it is saved.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplicationPolicy {
+    //sent to every client in range and saved
+    Public,
+    //only sent to the client whose player entity has the component, and saved
+    OwnerOnly,
+    //saved but never sent to clients
+    ServerOnly,
+    //sent to every client in range but never saved
+    NotPersisted,
+}
+
+impl ReplicationPolicy {
+    pub fn is_replicated(&self) -> bool {
+        *self != ReplicationPolicy::ServerOnly
+    }
+    pub fn is_persisted(&self) -> bool {
+        *self != ReplicationPolicy::NotPersisted
+    }
+}
+
+/**
+ * Maps a network component id to the reflect name it was hashed from.
+ */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ComponentTypeInfo {
+    pub type_id: ComponentTypeId,
+    pub type_name: String,
+}
+
 impl ComponentTypeId {
     pub fn new_with_number(id: u64) -> Self {
         ComponentTypeId(id)
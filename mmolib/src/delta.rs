@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use crate::component::ComponentTypeId;
+use crate::entity_id::EntityId;
+use crate::server_response_type::{ComponentUpdate, ComponentUpdateType};
+
+/**
+ * The fields of new that differ from old, or None if either isn't an object and has to be sent whole.
+ */
+pub fn diff_fields(old: &Value, new: &Value) -> Option<Map<String, Value>> {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => Some(
+            new.iter()
+                .filter(|(field, value)| old.get(*field) != Some(*value))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/**
+ * Applies a field delta on top of the last full value, what a client does with a Patched update.
+ */
+pub fn apply_fields(base: &mut Value, fields: &Map<String, Value>) {
+    if let Value::Object(base) = base {
+        for (field, value) in fields {
+            base.insert(field.clone(), value.clone());
+        }
+    }
+}
+
+/**
+ * Remembers the last value of every component sent to one client so changes can be sent as field deltas.
+ */
+#[derive(Default)]
+pub struct DeltaCache {
+    last_sent: HashMap<(EntityId, ComponentTypeId), Value>,
+}
+
+impl DeltaCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /**
+     * Turns Changed updates into Patched ones against what this client last got. Returns None when nothing the client sees changed.
+     */
+    pub fn compress(&mut self, update: ComponentUpdate) -> Option<ComponentUpdate> {
+        let key = (update.get_entity_id(), update.get_component_type_id());
+        match update.get_component_update_info() {
+            ComponentUpdateType::Removed => {
+                self.last_sent.remove(&key);
+                Some(update)
+            }
+            ComponentUpdateType::Added { packet } => {
+                self.last_sent.insert(key, packet.clone());
+                Some(update)
+            }
+            ComponentUpdateType::Changed { packet } => {
+                let fields = self
+                    .last_sent
+                    .get(&key)
+                    .and_then(|last| diff_fields(last, packet));
+                self.last_sent.insert(key, packet.clone());
+                match fields {
+                    Some(fields) if fields.is_empty() => None,
+                    Some(fields) => Some(ComponentUpdate::new(
+                        key.0,
+                        key.1,
+                        ComponentUpdateType::Patched { fields: fields },
+                    )),
+                    None => Some(update),
+                }
+            }
+            ComponentUpdateType::Patched { .. } => Some(update),
+        }
+    }
+    /**
+     * Drops everything cached for an entity the client no longer knows about.
+     */
+    pub fn forget_entity(&mut self, entity_id: EntityId) {
+        self.last_sent.retain(|(id, _), _| *id != entity_id);
+    }
+}
+
+#[test]
+fn test_delta_cache() {
+    use serde_json::json;
+    let id = EntityId::new_with_number(5);
+    let type_id = ComponentTypeId::new_with_number(9);
+    let mut cache = DeltaCache::new();
+    let added = json!({"pos": [1, 2], "load_with_chunk": true});
+    cache.compress(ComponentUpdate::new(
+        id,
+        type_id,
+        ComponentUpdateType::Added {
+            packet: added.clone(),
+        },
+    ));
+    let changed = json!({"pos": [1, 3], "load_with_chunk": true});
+    let patch = cache
+        .compress(ComponentUpdate::new(
+            id,
+            type_id,
+            ComponentUpdateType::Changed {
+                packet: changed.clone(),
+            },
+        ))
+        .unwrap();
+    match patch.get_component_update_info() {
+        ComponentUpdateType::Patched { fields } => {
+            assert_eq!(fields.len(), 1);
+            let mut client = added;
+            apply_fields(&mut client, fields);
+            assert_eq!(client, changed);
+        }
+        _ => panic!("Expected a patched update"),
+    }
+    assert!(cache
+        .compress(ComponentUpdate::new(
+            id,
+            type_id,
+            ComponentUpdateType::Changed { packet: changed },
+        ))
+        .is_none());
+}
@@ -1,6 +1,6 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Effect {
     Poison = 0,
     Fire = 1,
@@ -8,3 +8,38 @@ pub enum Effect {
     Stink = 3,
     Strength = 4,
 }
+
+impl Effect {
+    /**
+     * How many ticks a fresh application of the effect lasts.
+     */
+    pub fn default_duration(&self) -> u32 {
+        match self {
+            Effect::Poison => 50,
+            Effect::Fire => 20,
+            Effect::Haste => 100,
+            Effect::Stink => 200,
+            Effect::Strength => 100,
+        }
+    }
+    /**
+     * How many times the effect can stack on one entity. Reapplying an effect at its cap only refreshes the duration.
+     */
+    pub fn max_stacks(&self) -> u32 {
+        match self {
+            Effect::Poison => 5,
+            Effect::Strength => 3,
+            Effect::Fire | Effect::Haste | Effect::Stink => 1,
+        }
+    }
+    /**
+     * Damage dealt each tick per stack of the effect.
+     */
+    pub fn damage_per_tick(&self) -> u32 {
+        match self {
+            Effect::Poison => 1,
+            Effect::Fire => 3,
+            _ => 0,
+        }
+    }
+}
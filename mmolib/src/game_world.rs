@@ -7,21 +7,25 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
 
+use crate::block_type::BlockTypeId;
 use crate::chunk::{self, Chunk};
 use crate::chunk_map::ChunkMap;
 use crate::entity_id::EntityId;
 use crate::raws::RawTree;
 use crate::registry::Registry;
 use crate::uuid_map::{self, UuidMap};
-use crate::{chunk_generator, chunk_map, entity_deletion_list, player, position, position_map};
-use crate::{entity_id, uuid_system};
+use crate::{
+    chunk_generator, chunk_map, entity_deletion_list, input_ack, player, position, position_map,
+    save_tracker, timestep,
+};
+use crate::{entity_id, uuid_system, world_rules};
 //use crate::game;
 use crate::component;
 use crate::{raws, registry};
 use bevy_ecs::event::Events;
 use bevy_ecs::prelude::{Entity, IntoSystem, World};
 use bevy_ecs::schedule::{IntoSystemDescriptor, SystemStage};
-use bevy_ecs::world::EntityMut;
+use bevy_ecs::world::{EntityMut, Mut};
 use serde_json::Value;
 
 type EventUpdateClosure = Box<dyn Fn(&mut World) -> () + Send + Sync>;
@@ -52,7 +56,6 @@ impl GameWorldBuilder {
             SystemStage::parallel()
                 .with_system(uuid_system::uuid_system)
                 .with_system(position_map::update_position_map_on_position_change)
-                .with_system(position_map::update_position_test_method)
                 .with_system(position_map::update_position_map_on_position_removal),
         );
         let mut world = bevy_ecs::world::World::default();
@@ -60,6 +63,10 @@ impl GameWorldBuilder {
         world.insert_resource(position_map::PositionMap::new());
         world.insert_resource(chunk_map::ChunkMap::new());
         world.insert_resource(entity_deletion_list::EntityDeletionList::new());
+        world.insert_resource(world_rules::WorldRules::new());
+        world.insert_resource(save_tracker::SaveTracker::new());
+        world.insert_resource(input_ack::InputAcks::new());
+        world.insert_resource(timestep::DeltaTime::new(timestep::DEFAULT_TICK_RATE));
         GameWorldBuilder {
             world: GameWorld {
                 world: world,
@@ -107,10 +114,24 @@ impl GameWorldBuilder {
         self.world.world_id = world_id;
         self
     }
+    pub fn with_registry(mut self, registry: Arc<Registry>) -> Self {
+        self.world.world.insert_resource(registry);
+        self
+    }
+    pub fn with_world_rules(mut self, rules: world_rules::WorldRules) -> Self {
+        self.world.world.insert_resource(rules);
+        self
+    }
     pub fn with_raws(mut self, raws: RawTree) -> Self {
         self.world.world.insert_resource(raws);
         self
     }
+    pub fn with_tick_rate(mut self, tick_rate: u32) -> Self {
+        self.world
+            .world
+            .insert_resource(timestep::DeltaTime::new(tick_rate));
+        self
+    }
     pub fn with_render_distance(mut self, render_distance: i64) -> Self {
         self.world.render_distance = render_distance;
         self
@@ -143,6 +164,19 @@ impl GameWorld {
         r.insert(entity_id);
         r
     }
+    /**
+     * Spawns an entity that keeps an existing id, used when loading entities back into a world.
+     */
+    pub fn spawn_with_entity_id(&mut self, entity_id: EntityId) -> EntityMut<'_> {
+        let res = self.world.spawn().id();
+        self.world
+            .get_resource_mut::<uuid_map::UuidMap>()
+            .unwrap()
+            .add(entity_id, res);
+        let mut r = self.world.entity_mut(res);
+        r.insert(entity_id);
+        r
+    }
     pub fn get_render_distance(&self) -> i64 {
         self.render_distance
     }
@@ -153,6 +187,13 @@ impl GameWorld {
         });
     }
 
+    pub fn send_event<EventType: Send + Sync + 'static>(&mut self, event: EventType) {
+        self.world
+            .get_resource_mut::<Events<EventType>>()
+            .expect("Event type was not added to the world")
+            .send(event);
+    }
+
     pub fn get_world(&self) -> &bevy_ecs::world::World {
         &self.world
     }
@@ -214,6 +255,16 @@ impl GameWorld {
             .get_loaded_chunks()
     }
 
+    /**
+     * Changes a block in a loaded chunk, the change is saved and streamed to clients that have the chunk.
+     */
+    pub fn set_block(&mut self, position: chunk::Position, block: BlockTypeId) -> bool {
+        self.world
+            .get_resource_mut::<ChunkMap>()
+            .unwrap()
+            .set_block(position, block)
+    }
+
     pub fn unload_chunk(&mut self, chunk_id: chunk::ChunkId) -> Option<Chunk> {
         self.world
             .get_resource_mut::<ChunkMap>()
@@ -225,14 +276,75 @@ impl GameWorld {
         self.world.get_resource::<uuid_map::UuidMap>().unwrap()
     }
 
+    pub fn get_delta_time(&self) -> timestep::DeltaTime {
+        *self.world.get_resource::<timestep::DeltaTime>().unwrap()
+    }
+
+    pub fn set_tick(&mut self, tick: u64) {
+        self.world
+            .get_resource_mut::<timestep::DeltaTime>()
+            .unwrap()
+            .set_tick(tick);
+    }
+
+    pub fn get_input_acks(&self) -> &input_ack::InputAcks {
+        self.world.get_resource::<input_ack::InputAcks>().unwrap()
+    }
+
+    pub fn get_input_acks_mut(&mut self) -> Mut<'_, input_ack::InputAcks> {
+        self.world
+            .get_resource_mut::<input_ack::InputAcks>()
+            .unwrap()
+    }
+
     pub fn get_raws(&self) -> &raws::RawTree {
         self.world.get_resource::<raws::RawTree>().unwrap()
     }
 
+    pub fn get_world_rules(&self) -> &world_rules::WorldRules {
+        self.world
+            .get_resource::<world_rules::WorldRules>()
+            .unwrap()
+    }
+
+    pub fn set_world_rules(&mut self, rules: world_rules::WorldRules) {
+        self.world.insert_resource(rules);
+    }
+
     pub fn get_chunk_map(&self) -> &chunk_map::ChunkMap {
         self.world.get_resource::<chunk_map::ChunkMap>().unwrap()
     }
 
+    /**
+     * Carries this tick's chunk and component changes over to the SaveTracker, must run before clear_trackers.
+     */
+    pub fn record_unsaved_changes(&mut self) {
+        let changed_chunks: Vec<chunk::ChunkId> = self
+            .get_chunk_map()
+            .get_loaded_chunks()
+            .into_iter()
+            .filter(|c| self.get_chunk_map().is_chunk_changed(**c))
+            .copied()
+            .collect();
+        let mut tracker = self
+            .world
+            .get_resource_mut::<save_tracker::SaveTracker>()
+            .unwrap();
+        for chunk_id in changed_chunks {
+            tracker.mark_chunk(chunk_id);
+        }
+        if let Some(registry) = self.world.get_resource::<Arc<Registry>>().cloned() {
+            registry.record_save_changes(&mut self.world);
+        }
+    }
+
+    pub fn take_unsaved_changes(&mut self) -> save_tracker::SaveTracker {
+        self.world
+            .get_resource_mut::<save_tracker::SaveTracker>()
+            .unwrap()
+            .take()
+    }
+
     pub fn clear_trackers(&mut self) -> () {
         self.world.clear_trackers();
         self.world
@@ -0,0 +1,29 @@
+use bevy_ecs::prelude::Component;
+use bevy_reflect::Reflect;
+use bevy_reflect::ReflectDeserialize;
+use serde::{Deserialize, Serialize};
+
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Self {
+            current: max,
+            max: max,
+        }
+    }
+    pub fn damage(&mut self, amount: u32) {
+        self.current = self.current.saturating_sub(amount);
+    }
+    pub fn heal(&mut self, amount: u32) {
+        self.current = self.current.saturating_add(amount).min(self.max);
+    }
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use bevy_ecs::prelude::*;
+
+use crate::entity_id::EntityId;
+
+/**
+ * The newest input sequence number received from each player and the newest one the simulation has run, which is acknowledged in Ticked.
+ */
+#[derive(Default)]
+pub struct InputAcks {
+    received: HashMap<EntityId, u64>,
+    processed: HashMap<EntityId, u64>,
+}
+
+impl InputAcks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn record(&mut self, player: EntityId, sequence: u64) {
+        let newest = self.received.entry(player).or_insert(sequence);
+        *newest = (*newest).max(sequence);
+    }
+    pub fn get_processed(&self, player: EntityId) -> Option<u64> {
+        self.processed.get(&player).copied()
+    }
+    pub fn remove(&mut self, player: EntityId) {
+        self.received.remove(&player);
+        self.processed.remove(&player);
+    }
+}
+
+/**
+ * Inputs are sent as events before the pre update stage, so by the time this runs in that stage they have been applied.
+ */
+pub fn acknowledge_inputs_system(mut acks: ResMut<InputAcks>) {
+    let received: Vec<(EntityId, u64)> = acks.received.drain().collect();
+    acks.processed.extend(received);
+}
+
+#[test]
+fn test_input_acks() {
+    let player = EntityId::new_with_number(3);
+    let mut world = World::new();
+    world.insert_resource(InputAcks::new());
+    let mut stage = SystemStage::parallel().with_system(acknowledge_inputs_system);
+    world.resource_mut::<InputAcks>().record(player, 4);
+    world.resource_mut::<InputAcks>().record(player, 2);
+    assert_eq!(world.resource::<InputAcks>().get_processed(player), None);
+    stage.run(&mut world);
+    assert_eq!(world.resource::<InputAcks>().get_processed(player), Some(4));
+}
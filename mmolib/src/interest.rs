@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::chunk::ChunkId;
+use crate::delta::DeltaCache;
+use crate::entity_id::EntityId;
+
+/**
+ * The entities or chunks a client currently knows about. Anything entering it is sent in full, anything leaving it is removed on the client.
+ */
+pub struct InterestSet<T> {
+    members: HashSet<T>,
+}
+
+pub struct InterestChange<T> {
+    pub entered: Vec<T>,
+    pub stayed: Vec<T>,
+    pub left: Vec<T>,
+}
+
+/**
+ * Everything a single connection has been sent.
+ */
+#[derive(Default)]
+pub struct ClientInterest {
+    pub entities: InterestSet<EntityId>,
+    pub chunks: InterestSet<ChunkId>,
+    //only kept for clients that negotiated delta compression
+    pub deltas: Option<DeltaCache>,
+}
+
+impl<T> Default for InterestSet<T> {
+    fn default() -> Self {
+        Self {
+            members: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Eq + Hash + Copy> InterestSet<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn contains(&self, member: T) -> bool {
+        self.members.contains(&member)
+    }
+    /**
+     * Replaces the set with what is visible this tick and returns how it changed.
+     */
+    pub fn update(&mut self, visible: HashSet<T>) -> InterestChange<T> {
+        let mut change = InterestChange {
+            entered: Vec::new(),
+            stayed: Vec::new(),
+            left: Vec::new(),
+        };
+        for member in &visible {
+            if self.members.contains(member) {
+                change.stayed.push(*member);
+            } else {
+                change.entered.push(*member);
+            }
+        }
+        change.left = self.members.difference(&visible).copied().collect();
+        self.members = visible;
+        change
+    }
+}
+
+#[test]
+fn test_interest_set() {
+    let a = EntityId::new_with_number(1);
+    let b = EntityId::new_with_number(2);
+    let c = EntityId::new_with_number(3);
+    let mut interest = InterestSet::new();
+    let change = interest.update([a, b].into_iter().collect());
+    assert_eq!(change.entered.len(), 2);
+    assert!(change.left.is_empty());
+    let change = interest.update([b, c].into_iter().collect());
+    assert_eq!(change.entered, vec![c]);
+    assert_eq!(change.stayed, vec![b]);
+    assert_eq!(change.left, vec![a]);
+    assert!(!interest.contains(a));
+}
@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use bevy_reflect::Reflect;
+use bevy_reflect::ReflectDeserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::active_effects::ActiveEffects;
+use crate::chunk;
+use crate::entity_deletion_list::EntityDeletionList;
+use crate::entity_id::EntityId;
+use crate::game_world::GameWorld;
+use crate::health::Health;
+use crate::item_type::{ItemTypeId, ItemUseAction};
+use crate::position;
+use crate::registry::Registry;
+
+/**
+ * How far away, in tiles, an item can be picked up from or used on a target.
+ */
+pub const PICKUP_RANGE: u32 = 1;
+pub const USE_RANGE: u32 = 5;
+
+/**
+ * An item entity. Items lying in the world have a position, carried items don't.
+ */
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct Item {
+    pub item_type: ItemTypeId,
+}
+
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct Inventory {
+    items: Vec<EntityId>,
+    capacity: usize,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: Vec::new(),
+            capacity: capacity,
+        }
+    }
+    pub fn add(&mut self, item: EntityId) -> bool {
+        if self.is_full() || self.contains(item) {
+            return false;
+        }
+        self.items.push(item);
+        true
+    }
+    pub fn remove(&mut self, item: EntityId) -> bool {
+        let len = self.items.len();
+        self.items.retain(|x| *x != item);
+        len != self.items.len()
+    }
+    pub fn contains(&self, item: EntityId) -> bool {
+        self.items.contains(&item)
+    }
+    pub fn is_full(&self) -> bool {
+        self.items.len() >= self.capacity
+    }
+    pub fn items(&self) -> &Vec<EntityId> {
+        &self.items
+    }
+}
+
+pub struct PickupEvent {
+    pub actor: Entity,
+    pub item: Entity,
+}
+
+pub struct DropEvent {
+    pub actor: Entity,
+    pub item: Entity,
+}
+
+pub struct UseItemEvent {
+    pub actor: Entity,
+    pub item: Entity,
+    pub target: Entity,
+}
+
+/**
+ * Spawns an item lying on the ground at a position.
+ */
+pub fn spawn_item(
+    world: &mut GameWorld,
+    item_type: ItemTypeId,
+    position: chunk::Position,
+) -> EntityId {
+    let mut e = world.spawn();
+    e.insert(Item {
+        item_type: item_type,
+    })
+    .insert(position::Position {
+        pos: position,
+        load_with_chunk: true,
+    });
+    *e.get::<EntityId>().unwrap()
+}
+
+/**
+ * Spawns an item straight into an entity's inventory. If the entity has no room the item is left on the ground at its feet.
+ * Returns the new item and whether it is carried, or None if the entity has no position.
+ */
+pub fn give_item(
+    world: &mut GameWorld,
+    entity_id: EntityId,
+    item_type: ItemTypeId,
+) -> Option<(EntityId, bool)> {
+    let entity = *world.get_uuid_map().get(entity_id)?;
+    let position = world.get_world().get::<position::Position>(entity)?.pos;
+    let item = spawn_item(world, item_type, position);
+    let carried = match world.get_world_mut().get_mut::<Inventory>(entity) {
+        Some(mut inventory) => inventory.add(item),
+        None => false,
+    };
+    if carried {
+        let item_entity = *world.get_uuid_map().get(item).unwrap();
+        world
+            .get_world_mut()
+            .entity_mut(item_entity)
+            .remove::<position::Position>();
+    }
+    Some((item, carried))
+}
+
+/**
+ * Gets the ids of the items carried by an entity, if it has an inventory.
+ */
+pub fn get_carried_items(world: &GameWorld, entity_id: EntityId) -> Vec<EntityId> {
+    world
+        .get_uuid_map()
+        .get(entity_id)
+        .and_then(|entity| world.get_world().get::<Inventory>(*entity))
+        .map(|inventory| inventory.items().clone())
+        .unwrap_or_default()
+}
+
+/**
+ * Despawns an entity along with every item it carries.
+ */
+pub fn despawn_with_carried_items(world: &mut GameWorld, entity_id: EntityId) {
+    let mut to_despawn = vec![entity_id];
+    while let Some(id) = to_despawn.pop() {
+        to_despawn.extend(get_carried_items(world, id));
+        world.despawn_entity_by_entity_id(id);
+    }
+}
+
+fn within_range(
+    a: Option<&position::Position>,
+    b: Option<&position::Position>,
+    range: u32,
+) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => chunk::tile_distance_between_position(a.pos, b.pos) <= range,
+        _ => false,
+    }
+}
+
+pub fn pickup_system(
+    mut pickup_events: EventReader<PickupEvent>,
+    mut actors: Query<(&mut Inventory, &position::Position)>,
+    items: Query<(&EntityId, Option<&position::Position>), With<Item>>,
+    mut commands: Commands,
+) {
+    //the position is only removed once commands are applied, so track items picked up during this run
+    let mut claimed = HashSet::new();
+    for event in pickup_events.iter() {
+        if claimed.contains(&event.item) {
+            continue;
+        }
+        let (item_id, item_position) = match items.get(event.item) {
+            Ok(item) => item,
+            Err(_) => continue,
+        };
+        if let Ok((mut inventory, actor_position)) = actors.get_mut(event.actor) {
+            //items without a position are already being carried
+            if !within_range(Some(actor_position), item_position, PICKUP_RANGE) {
+                continue;
+            }
+            if inventory.add(*item_id) {
+                claimed.insert(event.item);
+                commands.entity(event.item).remove::<position::Position>();
+            }
+        }
+    }
+}
+
+pub fn drop_system(
+    mut drop_events: EventReader<DropEvent>,
+    mut actors: Query<(&mut Inventory, &position::Position)>,
+    items: Query<&EntityId, With<Item>>,
+    mut commands: Commands,
+) {
+    for event in drop_events.iter() {
+        let item_id = match items.get(event.item) {
+            Ok(item_id) => item_id,
+            Err(_) => continue,
+        };
+        if let Ok((mut inventory, actor_position)) = actors.get_mut(event.actor) {
+            if inventory.remove(*item_id) {
+                commands.entity(event.item).insert(position::Position {
+                    pos: actor_position.pos,
+                    load_with_chunk: true,
+                });
+            }
+        }
+    }
+}
+
+pub fn use_item_system(
+    mut use_events: EventReader<UseItemEvent>,
+    registry: Res<Arc<Registry>>,
+    mut deletion_list: ResMut<EntityDeletionList>,
+    mut actors: Query<&mut Inventory>,
+    items: Query<(&EntityId, &Item)>,
+    positions: Query<&position::Position>,
+    mut targets: Query<(Option<&mut ActiveEffects>, Option<&mut Health>)>,
+    mut commands: Commands,
+) {
+    for event in use_events.iter() {
+        let (item_id, item) = match items.get(event.item) {
+            Ok(item) => item,
+            Err(_) => continue,
+        };
+        let item_type = match registry.get_item_type_by_id(item.item_type) {
+            Some(item_type) => item_type,
+            None => continue,
+        };
+        match actors.get_mut(event.actor) {
+            Ok(inventory) if inventory.contains(*item_id) => {}
+            _ => continue,
+        }
+        if !within_range(
+            positions.get(event.actor).ok(),
+            positions.get(event.target).ok(),
+            USE_RANGE,
+        ) {
+            continue;
+        }
+        let (mut effects, mut health) = match targets.get_mut(event.target) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let mut new_effects: Option<ActiveEffects> = None;
+        for action in item_type.get_use_actions() {
+            match action {
+                ItemUseAction::ApplyEffect { effect, duration } => {
+                    let duration = duration.unwrap_or(effect.default_duration());
+                    match effects.as_mut() {
+                        Some(effects) => effects.apply(*effect, duration),
+                        None => new_effects
+                            .get_or_insert_with(ActiveEffects::new)
+                            .apply(*effect, duration),
+                    }
+                }
+                ItemUseAction::Heal { amount } => {
+                    if let Some(health) = health.as_mut() {
+                        health.heal(*amount);
+                    }
+                }
+            }
+        }
+        if let Some(new_effects) = new_effects {
+            commands.entity(event.target).insert(new_effects);
+        }
+        if item_type.is_consumable() {
+            if let Ok(mut inventory) = actors.get_mut(event.actor) {
+                inventory.remove(*item_id);
+            }
+            deletion_list.add(*item_id);
+        }
+    }
+}
+
+#[test]
+fn test_inventory_capacity() {
+    let mut inventory = Inventory::new(2);
+    let a = EntityId::new_with_number(1);
+    assert!(inventory.add(a));
+    assert!(!inventory.add(a));
+    assert!(inventory.add(EntityId::new_with_number(2)));
+    assert!(!inventory.add(EntityId::new_with_number(3)));
+    assert!(inventory.remove(a));
+    assert!(!inventory.contains(a));
+}
+
+#[test]
+fn test_give_item() {
+    let mut world = crate::game_world::GameWorldBuilder::new("give").build();
+    let mut e = world.spawn();
+    e.insert(position::Position {
+        pos: (3, 4),
+        load_with_chunk: false,
+    })
+    .insert(Inventory::new(1));
+    let player = *e.get::<EntityId>().unwrap();
+    let (carried_item, carried) = give_item(&mut world, player, 1).unwrap();
+    assert!(carried);
+    assert_eq!(get_carried_items(&world, player), vec![carried_item]);
+    //no room left, so the second one ends up on the ground
+    let (dropped_item, carried) = give_item(&mut world, player, 1).unwrap();
+    assert!(!carried);
+    let dropped = *world.get_uuid_map().get(dropped_item).unwrap();
+    assert_eq!(
+        world
+            .get_world()
+            .get::<position::Position>(dropped)
+            .unwrap()
+            .pos,
+        (3, 4)
+    );
+}
+
+#[test]
+fn test_pickup_same_item_twice() {
+    let mut world = crate::game_world::GameWorldBuilder::new("pickup")
+        .add_event::<PickupEvent>()
+        .add_pre_update_system(pickup_system)
+        .build();
+    let mut actors = Vec::new();
+    for pos in [(3, 4), (3, 5)] {
+        let actor = world
+            .spawn()
+            .insert(position::Position {
+                pos: pos,
+                load_with_chunk: false,
+            })
+            .insert(Inventory::new(1))
+            .id();
+        actors.push(actor);
+    }
+    let item = world
+        .spawn()
+        .insert(Item { item_type: 1 })
+        .insert(position::Position {
+            pos: (3, 4),
+            load_with_chunk: true,
+        })
+        .id();
+    for actor in &actors {
+        world.send_event(PickupEvent {
+            actor: *actor,
+            item: item,
+        });
+    }
+    world.run_pre_update_scheduler();
+    let w = world.get_world();
+    let holders = actors
+        .iter()
+        .filter(|actor| !w.get::<Inventory>(**actor).unwrap().items().is_empty())
+        .count();
+    assert_eq!(holders, 1);
+    assert!(w.get::<position::Position>(item).is_none());
+}
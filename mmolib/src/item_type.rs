@@ -0,0 +1,53 @@
+use serde::Deserialize;
+
+use crate::effect;
+use crate::hashing::string_hash;
+use crate::{raws::Raw, resource};
+pub type ItemTypeId = u64;
+
+/**
+ * Something that happens to the target when an item is used on it.
+ */
+#[derive(Deserialize, Clone, Debug)]
+pub enum ItemUseAction {
+    ApplyEffect {
+        effect: effect::Effect,
+        duration: Option<u32>,
+    },
+    Heal {
+        amount: u32,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ItemType {
+    canonical_name: String,
+    descriptive_name: String,
+    resource: resource::ResourceId,
+    #[serde(default)]
+    consumable: bool,
+    #[serde(default)]
+    on_use: Vec<ItemUseAction>,
+}
+
+impl ItemType {
+    pub fn new(raw: &Raw) -> Result<ItemType, serde_json::Error> {
+        let res: ItemType = serde_json::from_value(raw.dat().clone())?;
+        Ok(res)
+    }
+    pub fn get_canonical_name(&self) -> &str {
+        &self.canonical_name
+    }
+    pub fn get_id(&self) -> ItemTypeId {
+        string_hash(&self.canonical_name)
+    }
+    pub fn get_descriptive_name(&self) -> &str {
+        &self.descriptive_name
+    }
+    pub fn is_consumable(&self) -> bool {
+        self.consumable
+    }
+    pub fn get_use_actions(&self) -> &Vec<ItemUseAction> {
+        &self.on_use
+    }
+}
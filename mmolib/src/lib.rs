@@ -5,25 +5,41 @@
 #![feature(specialization)]
 #![allow(unused)]
 #![deny(warnings)]
+pub mod active_effects;
 pub mod block_type;
 pub mod chunk;
 pub mod chunk_generator;
 pub mod chunk_map;
+pub mod codec;
+pub mod combat;
 pub mod component;
+pub mod delta;
 pub mod effect;
 pub mod entity_deletion_list;
 pub mod entity_id;
 pub mod game_world;
 pub mod hashing;
+pub mod health;
+pub mod input_ack;
+pub mod interest;
+pub mod inventory;
+pub mod item_type;
 pub mod movement_event;
 pub mod player;
 pub mod position;
 pub mod position_map;
+pub mod protocol;
 pub mod raws;
 pub mod registry;
 pub mod resource;
+pub mod respawn;
+pub mod save_tracker;
 pub mod server_request_type;
 pub mod server_response_type;
+pub mod terrain;
+pub mod timestep;
 pub mod util;
 pub mod uuid_map;
 mod uuid_system;
+pub mod world_rules;
+pub mod world_serializer;
@@ -1,8 +1,207 @@
-use bevy_ecs::prelude::Entity;
+use std::sync::Arc;
 
+use bevy_ecs::prelude::*;
+
+use crate::active_effects::ActiveEffects;
+use crate::block_type::BlockLayer;
+use crate::chunk;
+use crate::chunk_map::ChunkMap;
+use crate::position;
+use crate::registry::Registry;
 use crate::server_request_type::Direction;
+use crate::terrain::{self, Swimmer, TerrainEvent};
 
 pub struct MovementEvent {
     direction: Direction,
     entity: Entity,
 }
+
+impl MovementEvent {
+    pub fn new(entity: Entity, direction: Direction) -> Self {
+        Self {
+            direction: direction,
+            entity: entity,
+        }
+    }
+    pub fn get_direction(&self) -> Direction {
+        self.direction
+    }
+    pub fn get_entity(&self) -> Entity {
+        self.entity
+    }
+}
+
+/**
+ * Returns the position one tile away in the given direction. North is towards y = 0 and east is towards x = u32::MAX.
+ */
+pub fn offset_position(position: chunk::Position, direction: Direction) -> chunk::Position {
+    let (x, y) = position;
+    match direction {
+        Direction::North => (x, y.wrapping_sub(1)),
+        Direction::East => (x.wrapping_add(1), y),
+        Direction::South => (x, y.wrapping_add(1)),
+        Direction::West => (x.wrapping_sub(1), y),
+        Direction::Northeast => (x.wrapping_add(1), y.wrapping_sub(1)),
+        Direction::Southeast => (x.wrapping_add(1), y.wrapping_add(1)),
+        Direction::Southwest => (x.wrapping_sub(1), y.wrapping_add(1)),
+        Direction::Northwest => (x.wrapping_sub(1), y.wrapping_sub(1)),
+    }
+}
+
+/**
+ * What decides how far an entity gets when it moves. The server simulates moves with these and clients replay their unacknowledged moves with the same rules.
+ */
+pub struct MovementRules<'a> {
+    pub chunk_map: &'a ChunkMap,
+    pub registry: &'a Registry,
+    pub can_swim: bool,
+    pub steps: u32,
+}
+
+impl<'a> MovementRules<'a> {
+    pub fn new(
+        chunk_map: &'a ChunkMap,
+        registry: &'a Registry,
+        swimmer: Option<&Swimmer>,
+        effects: Option<&ActiveEffects>,
+    ) -> Self {
+        Self {
+            chunk_map: chunk_map,
+            registry: registry,
+            can_swim: swimmer.is_some(),
+            steps: effects.map_or(1, |x| x.movement_steps()),
+        }
+    }
+    /**
+     * The tiles entered in order, along with their layer. Stops before the first tile that can't be entered, or on the first pit.
+     */
+    pub fn walk(
+        &self,
+        from: chunk::Position,
+        direction: Direction,
+    ) -> Vec<(chunk::Position, BlockLayer)> {
+        let mut path = Vec::new();
+        let mut position = from;
+        for _ in 0..self.steps {
+            let target = offset_position(position, direction);
+            //entities can't walk into chunks that aren't loaded or blocks that aren't registered
+            let layer = match self
+                .chunk_map
+                .get_block(target)
+                .and_then(|block| self.registry.get_block_type_by_id(block))
+            {
+                Some(block_type) => block_type.get_layer(),
+                None => break,
+            };
+            if !terrain::can_enter(&layer, self.can_swim) {
+                break;
+            }
+            position = target;
+            let fell = matches!(layer, BlockLayer::Pit);
+            path.push((target, layer));
+            //falling into a pit ends the move, however many steps are left
+            if fell {
+                break;
+            }
+        }
+        path
+    }
+    /**
+     * Where a move ends up.
+     */
+    pub fn destination(&self, from: chunk::Position, direction: Direction) -> chunk::Position {
+        self.walk(from, direction)
+            .last()
+            .map_or(from, |(position, _)| *position)
+    }
+}
+
+pub fn movement_system(
+    mut movement_events: EventReader<MovementEvent>,
+    mut terrain_events: EventWriter<TerrainEvent>,
+    chunk_map: Res<ChunkMap>,
+    registry: Res<Arc<Registry>>,
+    mut query: Query<(
+        &mut position::Position,
+        Option<&Swimmer>,
+        Option<&ActiveEffects>,
+    )>,
+) {
+    for event in movement_events.iter() {
+        match query.get_mut(event.entity) {
+            Ok((mut position, swimmer, effects)) => {
+                let rules = MovementRules::new(&chunk_map, &registry, swimmer, effects);
+                for (target, layer) in rules.walk(position.pos, event.direction) {
+                    position.pos = target;
+                    if let Some(terrain_event) = terrain::terrain_event_for(&layer, event.entity) {
+                        terrain_events.send(terrain_event);
+                    }
+                }
+            }
+            Err(_) => {
+                tracing::warn!(
+                    "Movement event for entity {:?} which has no position",
+                    event.entity
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_offset_position() {
+    let p: chunk::Position = (10, 10);
+    assert_eq!(offset_position(p, Direction::North), (10, 9));
+    assert_eq!(offset_position(p, Direction::East), (11, 10));
+    assert_eq!(offset_position(p, Direction::South), (10, 11));
+    assert_eq!(offset_position(p, Direction::West), (9, 10));
+    assert_eq!(offset_position(p, Direction::Northeast), (11, 9));
+    assert_eq!(offset_position(p, Direction::Southeast), (11, 11));
+    assert_eq!(offset_position(p, Direction::Southwest), (9, 11));
+    assert_eq!(offset_position(p, Direction::Northwest), (9, 9));
+}
+
+#[test]
+fn test_movement_rules() {
+    //the shipped raws have no pit, so add one next to a copy of the stone floor
+    let dir = std::env::temp_dir().join(format!("mmolib_movement_raws_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/../raws/stone.json"),
+        dir.join("stone.json"),
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("pit.json"),
+        r#"{"path":"block/pit","canonical_name":"pit","descriptive_name":"A pit","layer":"Pit","resource":"Dirt1"}"#,
+    )
+    .unwrap();
+    let registry = crate::registry::RegistryBuilder::new()
+        .load_block_raws(
+            &["block"],
+            &crate::raws::RawTree::new(dir.to_str().unwrap()),
+        )
+        .build();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let stone = registry.get_block_type("stonefloor").unwrap().get_id();
+    let pit = registry.get_block_type("pit").unwrap().get_id();
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.add(
+        chunk::chunk_id_from_position((0, 0)),
+        chunk::Chunk::new_from_array([[stone; chunk::CHUNK_SIZE]; chunk::CHUNK_SIZE]),
+    );
+    let mut rules = MovementRules::new(&chunk_map, &registry, None, None);
+    assert_eq!(rules.destination((5, 5), Direction::East), (6, 5));
+    rules.steps = 2;
+    assert_eq!(rules.destination((5, 5), Direction::East), (7, 5));
+    //the chunk to the east isn't loaded, so a hasted move stops at the edge
+    let edge = (chunk::CHUNK_SIZE as u32 - 2, 5);
+    assert_eq!(rules.walk(edge, Direction::East).len(), 1);
+    //a hasted move ends in the pit it falls into
+    chunk_map.set_block((6, 5), pit);
+    let rules = MovementRules {
+        steps: 3,
+        ..MovementRules::new(&chunk_map, &registry, None, None)
+    };
+    assert_eq!(rules.destination((5, 5), Direction::East), (6, 5));
+}
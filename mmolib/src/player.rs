@@ -7,12 +7,20 @@ use serde::{Deserialize, Serialize};
 #[reflect_value(Serialize, PartialEq, Deserialize)]
 pub struct Player {
     pub username: String,
-    pub last_ping_timestamp: u64,
 }
 
-impl Player {
+/**
+ * When the player's client was last heard from. Kept apart from Player so it is never sent to other clients.
+ */
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct LastPing {
+    pub timestamp: u64,
+}
+
+impl LastPing {
     pub fn update_timestamp(&mut self) {
-        self.last_ping_timestamp = std::time::SystemTime::now()
+        self.timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
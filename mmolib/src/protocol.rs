@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+/**
+ * Bumped whenever ServerRequestType or ServerResponseType change in a way old clients can't read.
+ */
+pub const PROTOCOL_VERSION: u32 = 8;
+/**
+ * Oldest client protocol version the server still accepts. Version 7 added ServerShutdown and version 8 added CommandOutput and the command error codes, which the server pushes to every client.
+ */
+pub const MIN_PROTOCOL_VERSION: u32 = 8;
+/**
+ * Changed components are sent as Patched updates holding only the fields that changed.
+ */
+pub const FEATURE_DELTA_COMPRESSION: &str = "delta_compression";
+/**
+ * Optional features the server can enable for a client that asks for them in its hello.
+ */
+pub const SUPPORTED_FEATURES: &[&str] = &[FEATURE_DELTA_COMPRESSION];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "reason")]
+pub enum HandshakeError {
+    HelloRequired,
+    UnsupportedProtocolVersion { client_version: u32 },
+}
+
+pub fn check_protocol_version(client_version: u32) -> Result<(), HandshakeError> {
+    if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&client_version) {
+        Ok(())
+    } else {
+        Err(HandshakeError::UnsupportedProtocolVersion {
+            client_version: client_version,
+        })
+    }
+}
+
+/**
+ * The features both sides support, unknown ones requested by newer clients are dropped.
+ */
+pub fn negotiate_features(requested: &[String]) -> Vec<String> {
+    requested
+        .iter()
+        .filter(|f| SUPPORTED_FEATURES.contains(&f.as_str()))
+        .cloned()
+        .collect()
+}
+
+#[test]
+fn test_handshake() {
+    assert!(check_protocol_version(PROTOCOL_VERSION).is_ok());
+    assert!(check_protocol_version(7).is_err());
+    assert_eq!(
+        check_protocol_version(PROTOCOL_VERSION + 1),
+        Err(HandshakeError::UnsupportedProtocolVersion {
+            client_version: PROTOCOL_VERSION + 1
+        })
+    );
+    assert_eq!(
+        negotiate_features(&[
+            "not_a_feature".to_owned(),
+            FEATURE_DELTA_COMPRESSION.to_owned()
+        ]),
+        vec![FEATURE_DELTA_COMPRESSION.to_owned()]
+    );
+}
@@ -2,19 +2,24 @@ use std::sync::Arc;
 use std::{collections::HashMap, fmt};
 
 use crate::block_type::BlockType;
-use crate::component::{get_type_id, get_type_id_from_str, ComponentTypeId};
+use crate::component::{
+    get_type_id, get_type_id_from_str, ComponentTypeId, ComponentTypeInfo, ReplicationPolicy,
+};
 use crate::entity_id::EntityId;
 use crate::game_world::GameWorld;
 use crate::raws::Raw;
 use crate::server_response_type::{ComponentUpdate, ComponentUpdateType};
 use crate::uuid_map::UuidMap;
+use crate::{
+    active_effects, combat, hashing, health, inventory, item_type, player, position, save_tracker,
+    terrain,
+};
 use crate::{
     block_type,
     component::{self},
     entity_id,
     raws::RawTree,
 };
-use crate::{hashing, player, position};
 use bevy_ecs::component::ComponentId;
 use bevy_ecs::prelude::{Component, Entity, ReflectComponent};
 use bevy_ecs::query::{ChangeTrackers, Changed};
@@ -29,9 +34,21 @@ pub type ComponentSerializationFunction =
     fn(entity: &mut EntityMut, json: Value) -> Result<(), serde_json::Error>;
 
 pub type NetworkChangeDetectionQuery = fn(world: &mut World) -> Vec<(EntityId, ComponentUpdate)>;
+/**
+ * Serializes an entity's component the same way the network change detectors do, if it has one.
+ */
+pub type NetworkSnapshotFunction = fn(world: &World, entity: Entity) -> Option<Value>;
+/**
+ * Returns the entities whose component was added or changed, and the entities it was removed from.
+ */
+pub type SaveChangeDetectionQuery = fn(world: &mut World) -> (Vec<EntityId>, Vec<EntityId>);
 pub struct Registry {
     block_types: HashMap<block_type::BlockTypeId, block_type::BlockType>,
+    item_types: HashMap<item_type::ItemTypeId, item_type::ItemType>,
     network_change_detectors: HashMap<ComponentTypeId, NetworkChangeDetectionQuery>,
+    network_snapshot_funcs: HashMap<ComponentTypeId, NetworkSnapshotFunction>,
+    replication_policies: HashMap<ComponentTypeId, ReplicationPolicy>,
+    save_change_detectors: HashMap<&'static str, SaveChangeDetectionQuery>,
     type_registry: TypeRegistry,
     de_ser_funcs: HashMap<ComponentTypeId, ComponentSerializationFunction>,
 }
@@ -45,15 +62,29 @@ impl RegistryBuilder {
         let mut result = Self {
             registry: Registry {
                 block_types: HashMap::new(),
+                item_types: HashMap::new(),
                 type_registry: TypeRegistry::default(),
                 de_ser_funcs: HashMap::new(),
                 network_change_detectors: HashMap::new(),
+                network_snapshot_funcs: HashMap::new(),
+                replication_policies: HashMap::new(),
+                save_change_detectors: HashMap::new(),
             },
         };
         //add default components
         result = result.with_component::<position::Position>();
         result = result.with_component::<player::Player>();
+        result =
+            result.with_component_and_policy::<player::LastPing>(ReplicationPolicy::ServerOnly);
         result = result.with_component::<entity_id::EntityId>();
+        result = result.with_component::<terrain::Swimmer>();
+        result = result.with_component::<health::Health>();
+        result = result.with_component::<active_effects::ActiveEffects>();
+        result =
+            result.with_component_and_policy::<combat::CombatStats>(ReplicationPolicy::OwnerOnly);
+        result =
+            result.with_component_and_policy::<inventory::Inventory>(ReplicationPolicy::OwnerOnly);
+        result = result.with_component::<inventory::Item>();
         result
     }
     pub fn with_component_and_callback<
@@ -84,18 +115,73 @@ impl RegistryBuilder {
             + Reflect
             + Default
             + Serialize,
+    >(
+        self,
+    ) -> Self {
+        self.with_component_and_policy::<T>(ReplicationPolicy::Public)
+    }
+    pub fn with_component_and_policy<
+        T: 'static
+            + DeserializeOwned
+            + Component
+            + GetTypeRegistration
+            + Reflect
+            + Default
+            + Serialize,
     >(
         mut self,
+        policy: ReplicationPolicy,
     ) -> Self {
         self.register_reflect_component::<T>();
 
         self.add_deserialization_function::<T>();
 
-        self.add_network_update_function::<T>();
+        if policy.is_replicated() {
+            self.add_network_update_function::<T>();
+
+            self.add_network_snapshot_function::<T>();
+        }
+
+        if policy.is_persisted() {
+            self.add_save_change_function::<T>();
+        }
 
+        self.registry
+            .replication_policies
+            .insert(get_type_id::<T>(), policy);
         self
     }
 
+    fn add_network_snapshot_function<T: 'static + Component + Serialize>(&mut self) {
+        self.registry
+            .network_snapshot_funcs
+            .insert(get_type_id::<T>(), |w, entity| {
+                w.get::<T>(entity).map(|comp| {
+                    serde_json::to_value(comp).expect("Could not serialize in network function")
+                })
+            });
+    }
+
+    fn add_save_change_function<T: 'static + Component>(&mut self) {
+        //the reflect registration name is the type name, which is what components are stored under
+        self.registry
+            .save_change_detectors
+            .insert(std::any::type_name::<T>(), |w| {
+                let mut query = w.query_filtered::<Entity, Changed<T>>();
+                let map = w.get_resource::<UuidMap>().expect("UuidMap not in world");
+                let changed = query
+                    .iter(w)
+                    .filter_map(|entity| map.get_by_entity(entity))
+                    .collect();
+                //despawned entities are no longer in the uuid map, their rows are deleted or were saved on unload
+                let removed = w
+                    .removed::<T>()
+                    .filter_map(|e| map.get_by_entity(e))
+                    .collect();
+                (changed, removed)
+            });
+    }
+
     fn add_network_update_function<
         T: 'static
             + DeserializeOwned
@@ -195,6 +281,14 @@ impl RegistryBuilder {
         }
         self
     }
+    pub fn load_item_raws(mut self, path: &[&str], raws: &RawTree) -> RegistryBuilder {
+        for item_raws in raws.search_for_all(path) {
+            if let Some(item) = item_raws.get::<item_type::ItemType>() {
+                self.registry.item_types.insert(item.get_id(), item);
+            }
+        }
+        self
+    }
 
     pub fn build(self) -> Registry {
         self.registry
@@ -205,6 +299,21 @@ impl Registry {
     pub fn get_block_type(&self, canonical_name: &str) -> Option<&block_type::BlockType> {
         self.block_types.get(&hashing::string_hash(canonical_name))
     }
+    pub fn get_block_type_by_id(
+        &self,
+        block_type_id: block_type::BlockTypeId,
+    ) -> Option<&block_type::BlockType> {
+        self.block_types.get(&block_type_id)
+    }
+    pub fn get_item_type(&self, canonical_name: &str) -> Option<&item_type::ItemType> {
+        self.item_types.get(&hashing::string_hash(canonical_name))
+    }
+    pub fn get_item_type_by_id(
+        &self,
+        item_type_id: item_type::ItemTypeId,
+    ) -> Option<&item_type::ItemType> {
+        self.item_types.get(&item_type_id)
+    }
     pub fn type_registry(&self) -> &TypeRegistry {
         &self.type_registry
     }
@@ -229,6 +338,123 @@ impl Registry {
         });
         res
     }
+    /**
+     * Every replicated component of an entity as Added updates, for clients that have not seen the entity yet.
+     */
+    pub fn get_network_snapshot(
+        &self,
+        w: &World,
+        entity: Entity,
+        entity_id: EntityId,
+    ) -> Vec<ComponentUpdate> {
+        self.network_snapshot_funcs
+            .iter()
+            .filter_map(|(type_id, f)| {
+                f(w, entity).map(|packet| {
+                    ComponentUpdate::new(
+                        entity_id,
+                        *type_id,
+                        ComponentUpdateType::Added { packet: packet },
+                    )
+                })
+            })
+            .collect()
+    }
+    /**
+     * Records every component changed or removed since the trackers were last cleared into the SaveTracker resource.
+     */
+    pub fn record_save_changes(&self, w: &mut World) {
+        let mut changes = Vec::new();
+        for (name, f) in self.save_change_detectors.iter() {
+            let (changed, removed) = f(w);
+            changes.push((*name, changed, removed));
+        }
+        let mut tracker = w
+            .get_resource_mut::<save_tracker::SaveTracker>()
+            .expect("SaveTracker not in world");
+        for (name, changed, removed) in changes {
+            for entity_id in changed {
+                tracker.mark_changed(entity_id, name);
+            }
+            for entity_id in removed {
+                tracker.mark_removed(entity_id, name);
+            }
+        }
+    }
+    /**
+     * Components registered without a policy are public.
+     */
+    pub fn get_replication_policy(&self, type_id: ComponentTypeId) -> ReplicationPolicy {
+        self.replication_policies
+            .get(&type_id)
+            .copied()
+            .unwrap_or(ReplicationPolicy::Public)
+    }
+    /**
+     * Whether the client controlling the viewer entity may see this update.
+     */
+    pub fn is_visible_to(&self, update: &ComponentUpdate, viewer: Option<EntityId>) -> bool {
+        match self.get_replication_policy(update.get_component_type_id()) {
+            ReplicationPolicy::Public | ReplicationPolicy::NotPersisted => true,
+            ReplicationPolicy::OwnerOnly => viewer == Some(update.get_entity_id()),
+            ReplicationPolicy::ServerOnly => false,
+        }
+    }
+    /**
+     * Serializes every persisted component of an entity, keyed by reflect type name.
+     */
+    pub fn serialize_components(&self, w: &World, entity: Entity) -> Vec<(String, Value)> {
+        let mut res = Vec::new();
+        if let Some(entity_ref) = w.get_entity(entity) {
+            for id in entity_ref.archetype().components() {
+                //components that aren't registered, like bevy internals, aren't serialized
+                if let Some((name, reflect_component)) = w
+                    .components()
+                    .get_info(id)
+                    .and_then(|info| self.type_registry.get(info.type_id()?))
+                    .and_then(|registration| {
+                        Some((
+                            registration.name(),
+                            registration.data::<ReflectComponent>()?,
+                        ))
+                    })
+                {
+                    if !self
+                        .get_replication_policy(get_type_id_from_str(name))
+                        .is_persisted()
+                    {
+                        continue;
+                    }
+                    let reflect = reflect_component
+                        .reflect_component(w, entity)
+                        .and_then(|refl| refl.serializable())
+                        .unwrap();
+                    let ser = reflect.borrow();
+                    res.push((name.to_owned(), serde_json::to_value(&ser).unwrap()));
+                }
+            }
+        }
+        res
+    }
+    pub fn has_component_type(&self, type_name: &str) -> bool {
+        self.type_registry.get_with_name(type_name).is_some()
+    }
+    /**
+     * Every registered component's network id and reflect name, sorted by name.
+     */
+    pub fn component_types(&self) -> Vec<ComponentTypeInfo> {
+        let mut res: Vec<ComponentTypeInfo> = self
+            .type_registry
+            .iter()
+            .filter(|registration| registration.data::<ReflectComponent>().is_some())
+            .map(|registration| ComponentTypeInfo {
+                type_id: get_type_id_from_str(registration.name()),
+                type_name: registration.name().to_owned(),
+            })
+            .collect();
+        res.sort_by(|a, b| a.type_name.cmp(&b.type_name));
+        res
+    }
     pub fn add_component_to_entity(
         &self,
         entity: &mut EntityMut,
@@ -264,5 +490,72 @@ fn test_registry() {
     let rt = RawTree::new("./raws");
     let b = RegistryBuilder::new()
         .load_block_raws(&["block"], &rt)
+        .load_item_raws(&["item"], &rt)
+        .build();
+    let stone = b.get_block_type("stonefloor").unwrap();
+    assert_eq!(
+        b.get_block_type_by_id(stone.get_id())
+            .unwrap()
+            .get_canonical_name(),
+        "stonefloor"
+    );
+    assert!(b.get_item_type("healthpotion").unwrap().is_consumable());
+    let types = b.component_types();
+    let position = types
+        .iter()
+        .find(|t| t.type_name == std::any::type_name::<position::Position>())
+        .unwrap();
+    assert!(position.type_id == get_type_id::<position::Position>());
+    let owner = EntityId::new_with_number(1);
+    let inventory_update = ComponentUpdate::new(
+        owner,
+        get_type_id::<inventory::Inventory>(),
+        ComponentUpdateType::Removed,
+    );
+    assert!(b.is_visible_to(&inventory_update, Some(owner)));
+    assert!(!b.is_visible_to(&inventory_update, Some(EntityId::new_with_number(2))));
+}
+
+#[cfg(test)]
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+struct Transient {
+    value: u32,
+}
+
+#[test]
+fn test_replication_policies() {
+    let registry = RegistryBuilder::new()
+        .with_component_and_policy::<Transient>(ReplicationPolicy::NotPersisted)
         .build();
+    let owner = EntityId::new_with_number(1);
+    let other = EntityId::new_with_number(2);
+    let update = |type_id| ComponentUpdate::new(owner, type_id, ComponentUpdateType::Removed);
+    let public = update(get_type_id::<position::Position>());
+    assert!(registry.is_visible_to(&public, Some(other)));
+    assert!(registry.is_visible_to(&public, None));
+    let owner_only = update(get_type_id::<combat::CombatStats>());
+    assert!(registry.is_visible_to(&owner_only, Some(owner)));
+    assert!(!registry.is_visible_to(&owner_only, Some(other)));
+    assert!(!registry.is_visible_to(&owner_only, None));
+    let server_only = update(get_type_id::<player::LastPing>());
+    assert!(!registry.is_visible_to(&server_only, Some(owner)));
+    assert!(registry.is_visible_to(&update(get_type_id::<Transient>()), Some(other)));
+
+    //saves keep server only components and skip ones that aren't persisted
+    let mut world = World::new();
+    let entity = world
+        .spawn()
+        .insert(player::LastPing { timestamp: 5 })
+        .insert(Transient { value: 1 })
+        .id();
+    let saved: Vec<String> = registry
+        .serialize_components(&world, entity)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(
+        saved,
+        vec![std::any::type_name::<player::LastPing>().to_owned()]
+    );
 }
@@ -8,6 +8,7 @@ pub enum ResourceId {
     Grass1,
     Dirt1,
     AcidAnimation,
+    Potion1,
 }
 
 #[derive(Clone)]
@@ -32,6 +33,10 @@ pub fn spawn_resource_map() -> HashMap<ResourceId, ResourceType> {
             ResourceId::Grass1,
             ResourceType::StaticImage("images/sprite/Grass1.png"),
         ),
+        (
+            ResourceId::Potion1,
+            ResourceType::StaticImage("images/sprite/Potion1.png"),
+        ),
         (
             ResourceId::AcidAnimation,
             ResourceType::Animation(&["images/sprite/Acid1.png", "images/sprite/Acid2.png"]),
@@ -0,0 +1,105 @@
+use bevy_ecs::prelude::*;
+
+use crate::active_effects::ActiveEffects;
+use crate::combat::DeathEvent;
+use crate::health::Health;
+use crate::inventory::Inventory;
+use crate::player::Player;
+use crate::position;
+use crate::uuid_map::UuidMap;
+use crate::world_rules::WorldRules;
+
+/**
+ * Respawns dead players at the world's spawn point with full health. Unless the world keeps inventories, carried items are dropped where the player died.
+ */
+pub fn respawn_system(
+    mut death_events: EventReader<DeathEvent>,
+    rules: Res<WorldRules>,
+    uuid_map: Res<UuidMap>,
+    mut players: Query<
+        (
+            &mut Health,
+            &mut position::Position,
+            Option<&mut Inventory>,
+            Option<&mut ActiveEffects>,
+        ),
+        With<Player>,
+    >,
+    mut commands: Commands,
+) {
+    for event in death_events.iter() {
+        if let Ok((mut health, mut position, inventory, effects)) = players.get_mut(event.entity) {
+            if let Some(mut inventory) = inventory {
+                if !rules.keep_inventory && !inventory.items().is_empty() {
+                    for item in inventory.items().clone() {
+                        inventory.remove(item);
+                        if let Some(item_entity) = uuid_map.get(item) {
+                            commands.entity(*item_entity).insert(position::Position {
+                                pos: position.pos,
+                                load_with_chunk: true,
+                            });
+                        }
+                    }
+                }
+            }
+            if let Some(mut effects) = effects {
+                if !effects.is_empty() {
+                    *effects = ActiveEffects::new();
+                }
+            }
+            position.pos = rules.spawn_point;
+            health.current = health.max;
+        }
+    }
+}
+
+#[cfg(test)]
+fn respawn_with_item(keep_inventory: bool) -> (crate::game_world::GameWorld, Entity, Entity) {
+    let mut rules = WorldRules::new();
+    rules.keep_inventory = keep_inventory;
+    rules.spawn_point = (1, 1);
+    let mut world = crate::game_world::GameWorldBuilder::new("respawn")
+        .with_world_rules(rules)
+        .add_event::<DeathEvent>()
+        .add_post_update_system(respawn_system)
+        .build();
+    let mut e = world.spawn();
+    e.insert(Player {
+        username: "someone".to_owned(),
+    })
+    .insert(position::Position {
+        pos: (5, 5),
+        load_with_chunk: false,
+    })
+    .insert(Health {
+        current: 0,
+        max: 10,
+    })
+    .insert(Inventory::new(1));
+    let player = e.id();
+    let player_id = *e.get::<crate::entity_id::EntityId>().unwrap();
+    let (item_id, _) = crate::inventory::give_item(&mut world, player_id, 1).unwrap();
+    let item = *world.get_uuid_map().get(item_id).unwrap();
+    world.send_event(DeathEvent { entity: player });
+    world.run_post_update_scheduler();
+    let w = world.get_world();
+    assert_eq!(w.get::<position::Position>(player).unwrap().pos, (1, 1));
+    assert_eq!(w.get::<Health>(player).unwrap().current, 10);
+    (world, player, item)
+}
+
+#[test]
+fn test_respawn_drops_inventory() {
+    let (world, player, item) = respawn_with_item(false);
+    let w = world.get_world();
+    assert!(w.get::<Inventory>(player).unwrap().items().is_empty());
+    assert_eq!(w.get::<position::Position>(item).unwrap().pos, (5, 5));
+}
+
+#[test]
+fn test_respawn_keeps_inventory() {
+    let (world, player, item) = respawn_with_item(true);
+    let w = world.get_world();
+    assert_eq!(w.get::<Inventory>(player).unwrap().items().len(), 1);
+    assert!(w.get::<position::Position>(item).is_none());
+}
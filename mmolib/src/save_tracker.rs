@@ -0,0 +1,92 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::chunk::ChunkId;
+use crate::entity_id::EntityId;
+
+/**
+ * Accumulates what changed since the world was last saved. Bevy's change trackers are cleared every tick, but saves happen less often.
+ */
+#[derive(Default)]
+pub struct SaveTracker {
+    chunks: HashSet<ChunkId>,
+    changed_components: HashMap<EntityId, HashSet<String>>,
+    removed_components: HashMap<EntityId, HashSet<String>>,
+}
+
+impl SaveTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn mark_chunk(&mut self, chunk_id: ChunkId) {
+        self.chunks.insert(chunk_id);
+    }
+    pub fn mark_changed(&mut self, entity_id: EntityId, component: &str) {
+        if let Some(removed) = self.removed_components.get_mut(&entity_id) {
+            removed.remove(component);
+        }
+        self.changed_components
+            .entry(entity_id)
+            .or_default()
+            .insert(component.to_owned());
+    }
+    pub fn mark_removed(&mut self, entity_id: EntityId, component: &str) {
+        if let Some(changed) = self.changed_components.get_mut(&entity_id) {
+            changed.remove(component);
+        }
+        self.removed_components
+            .entry(entity_id)
+            .or_default()
+            .insert(component.to_owned());
+    }
+    pub fn get_chunks(&self) -> &HashSet<ChunkId> {
+        &self.chunks
+    }
+    /**
+     * Every entity with a changed or removed component.
+     */
+    pub fn get_entities(&self) -> HashSet<EntityId> {
+        self.changed_components
+            .keys()
+            .chain(self.removed_components.keys())
+            .copied()
+            .collect()
+    }
+    pub fn get_changed_components(&self, entity_id: EntityId) -> Option<&HashSet<String>> {
+        self.changed_components.get(&entity_id)
+    }
+    pub fn get_removed_components(&self, entity_id: EntityId) -> Option<&HashSet<String>> {
+        self.removed_components.get(&entity_id)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+            && self.changed_components.is_empty()
+            && self.removed_components.is_empty()
+    }
+    /**
+     * Takes every recorded change, leaving the tracker empty.
+     */
+    pub fn take(&mut self) -> SaveTracker {
+        std::mem::take(self)
+    }
+}
+
+#[test]
+fn test_save_tracker() {
+    let mut tracker = SaveTracker::new();
+    let e = EntityId::new_with_number(1);
+    tracker.mark_changed(e, "health");
+    tracker.mark_removed(e, "health");
+    assert!(tracker.get_changed_components(e).unwrap().is_empty());
+    assert!(tracker
+        .get_removed_components(e)
+        .unwrap()
+        .contains("health"));
+    tracker.mark_changed(e, "health");
+    assert!(!tracker
+        .get_removed_components(e)
+        .unwrap()
+        .contains("health"));
+    let taken = tracker.take();
+    assert!(tracker.is_empty());
+    assert_eq!(taken.get_entities().len(), 1);
+}
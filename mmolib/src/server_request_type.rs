@@ -1,9 +1,18 @@
 use serde::{Deserialize, Serialize};
 
+use crate::chunk::Position;
+use crate::codec::Codec;
 use crate::entity_id::EntityId;
+use crate::world_serializer::WorldSnapshot;
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum ServerRequestType {
+    Hello {
+        protocol_version: u32,
+        codec: Codec,
+        #[serde(default)]
+        features: Vec<String>,
+    },
     CreateGame {
         world_name: String,
     },
@@ -15,6 +24,12 @@ pub enum ServerRequestType {
         password: String,
     },
     Logout {},
+    RefreshSession {
+        refresh_token: String,
+    },
+    RevokeUserSessions {
+        user: String,
+    },
     Join {
         world_name: String,
     },
@@ -38,12 +53,38 @@ pub enum ServerRequestType {
         invite_code: Option<String>,
     },
     GetUserInviteCode {},
+    SetInviteQuota {
+        user: String,
+        quota: u32,
+    },
+    SetPvp {
+        world_name: String,
+        enabled: bool,
+    },
+    SetKeepInventory {
+        world_name: String,
+        enabled: bool,
+    },
+    SetSpawnPoint {
+        world_name: String,
+        position: Position,
+    },
     PlayerAction {
         world_name: String,
         action: PlayerActionType,
+        //increasing per client, acknowledged in Ticked once the action has been simulated
+        #[serde(default)]
+        input_sequence: Option<u64>,
+    },
+    ExportWorld {
+        world_name: String,
+    },
+    ImportWorld {
+        world_name: String,
+        snapshot: WorldSnapshot,
     },
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Direction {
     North,
     East,
@@ -3,8 +3,11 @@ use serde::{Deserialize, Serialize};
 use crate::{
     block_type::BlockTypeId,
     chunk::{Chunk, ChunkId, Position},
-    component::ComponentTypeId,
+    codec::Codec,
+    component::{ComponentTypeId, ComponentTypeInfo},
     entity_id::EntityId,
+    protocol::HandshakeError,
+    world_serializer::WorldSnapshot,
 };
 
 pub type EncodingType = serde_json::Value;
@@ -14,18 +17,40 @@ pub type EncodingType = serde_json::Value;
 pub enum ServerResponseType {
     AuthSuccess {
         session_token: String,
+        refresh_token: String,
+    },
+    Welcome {
+        protocol_version: u32,
+        codec: Codec,
+        component_types: Vec<ComponentTypeInfo>,
+        features: Vec<String>,
+    },
+    HandshakeRejected {
+        error: HandshakeError,
+        server_protocol_version: u32,
+        min_protocol_version: u32,
     },
     Ok {},
     AuthFailure {},
     TimedOut {},
     PermissionDenied {},
     Error {
-        message: &'static str,
+        code: ErrorCode,
+        detail: Option<String>,
     },
     Ticked {
         world_name: String,
+        tick: u64,
+        //the newest input_sequence of this client's PlayerActions that is reflected in this tick
+        last_processed_input: Option<u64>,
         component_updates: Vec<ComponentUpdate>,
         block_updates: Vec<BlockUpdate>,
+        //entities that left the client's area of interest or were despawned
+        removed_entities: Vec<EntityId>,
+        //chunks that came into render distance, sent in full once
+        loaded_chunks: Vec<ChunkPayload>,
+        //chunks the client should forget
+        unloaded_chunks: Vec<ChunkId>,
     },
 
     ChatMessage {
@@ -35,6 +60,77 @@ pub enum ServerResponseType {
     PlayerList {
         players: Vec<String>,
     },
+    InviteCode {
+        invite_code: String,
+        uses: u32,
+        expires_at: u64,
+    },
+    WorldSnapshot {
+        snapshot: WorldSnapshot,
+    },
+    //the server is going down, the socket is closed once every world is saved
+    ServerShutdown {},
+    //what a chat command printed, only sent to the client that ran it
+    CommandOutput {
+        output: String,
+    },
+}
+
+/**
+ * Stable error codes clients can branch on. The detail sent alongside is only meant for humans.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    MalformedRequest,
+    UnsupportedRequest,
+    WorldNotFound,
+    AlreadyExists,
+    UserNotFound,
+    NotLoggedIn,
+    NotJoined,
+    NotSpawned,
+    TargetNotFound,
+    RegistrationClosed,
+    InviteRequired,
+    InvalidInviteCode,
+    InviteQuotaReached,
+    NotInviteOnly,
+    InvalidSnapshot,
+    RateLimited,
+    UnknownCommand,
+    InvalidCommandArguments,
+    Kicked,
+    Banned,
+}
+
+impl ServerResponseType {
+    pub fn error(code: ErrorCode) -> Self {
+        ServerResponseType::Error {
+            code: code,
+            detail: None,
+        }
+    }
+    pub fn error_with_detail(code: ErrorCode, detail: &str) -> Self {
+        ServerResponseType::Error {
+            code: code,
+            detail: Some(detail.to_owned()),
+        }
+    }
+}
+
+/**
+ * The envelope around everything the server sends. Replies answer a single request and echo the request_id the client put on it, pushes like ticks and chat are sent unprompted.
+ */
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum ServerMessage {
+    Reply {
+        request_id: Option<u64>,
+        response: ServerResponseType,
+    },
+    Push {
+        response: ServerResponseType,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -49,8 +145,16 @@ pub struct ComponentUpdate {
 #[serde(tag = "type")]
 pub enum ComponentUpdateType {
     Removed,
-    Added { packet: EncodingType },
-    Changed { packet: EncodingType },
+    Added {
+        packet: EncodingType,
+    },
+    Changed {
+        packet: EncodingType,
+    },
+    //only the fields that changed since the last update this client got, see delta::DeltaCache
+    Patched {
+        fields: serde_json::Map<String, EncodingType>,
+    },
 }
 
 impl ComponentUpdate {
@@ -76,9 +180,15 @@ impl ComponentUpdate {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub struct BlockUpdate {
     pub block_pos: Position,
     pub block_type_id: BlockTypeId,
 }
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChunkPayload {
+    pub chunk_id: ChunkId,
+    pub chunk: Chunk,
+}
@@ -0,0 +1,60 @@
+use bevy_ecs::prelude::{Component, Entity};
+use bevy_reflect::Reflect;
+use bevy_reflect::ReflectDeserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::block_type::BlockLayer;
+use crate::effect;
+
+/**
+ * Marks an entity as able to move through water blocks.
+ */
+#[derive(Reflect, Default, Serialize, Deserialize, Clone, PartialEq, Debug, Component)]
+#[reflect_value(Serialize, PartialEq, Deserialize)]
+pub struct Swimmer {}
+
+/**
+ * Sent by the movement system when an entity moves onto a block with a consequence.
+ */
+pub enum TerrainEvent {
+    Fell {
+        entity: Entity,
+    },
+    SteppedOnEffect {
+        entity: Entity,
+        effect: effect::Effect,
+    },
+}
+
+/**
+ * Whether an entity may move onto a block of the given layer.
+ */
+pub fn can_enter(layer: &BlockLayer, can_swim: bool) -> bool {
+    match layer {
+        BlockLayer::Ground | BlockLayer::Pit | BlockLayer::Effect(_) => true,
+        BlockLayer::Water => can_swim,
+        BlockLayer::Solid => false,
+    }
+}
+
+/**
+ * The event caused by an entity arriving on a block of the given layer, if any.
+ */
+pub fn terrain_event_for(layer: &BlockLayer, entity: Entity) -> Option<TerrainEvent> {
+    match layer {
+        BlockLayer::Pit => Some(TerrainEvent::Fell { entity: entity }),
+        BlockLayer::Effect(effect) => Some(TerrainEvent::SteppedOnEffect {
+            entity: entity,
+            effect: *effect,
+        }),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_can_enter() {
+    assert!(can_enter(&BlockLayer::Ground, false));
+    assert!(!can_enter(&BlockLayer::Solid, true));
+    assert!(!can_enter(&BlockLayer::Water, false));
+    assert!(can_enter(&BlockLayer::Water, true));
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TICK_RATE: u32 = 20;
+/**
+ * A world that falls further behind than this runs this many ticks back to back and skips the rest.
+ */
+pub const MAX_CATCH_UP_TICKS: u64 = 5;
+
+/**
+ * The fixed simulated time between ticks and the current tick number, a resource systems can read instead of the wall clock.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct DeltaTime {
+    step: Duration,
+    tick: u64,
+}
+
+impl DeltaTime {
+    pub fn new(tick_rate: u32) -> Self {
+        Self {
+            step: Duration::from_secs(1) / tick_rate.max(1),
+            tick: 0,
+        }
+    }
+    pub fn get_step(&self) -> Duration {
+        self.step
+    }
+    pub fn seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+}
+
+/**
+ * Decides when ticks are due so a world ticks at a fixed rate no matter how long each tick takes.
+ */
+pub struct FixedTimestep {
+    step: Duration,
+    next_tick: Instant,
+    overruns: u64,
+    skipped_ticks: u64,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, now: Instant) -> Self {
+        Self {
+            step: step,
+            next_tick: now,
+            overruns: 0,
+            skipped_ticks: 0,
+        }
+    }
+    /**
+     * How many ticks to run now. Ticks missed while the server was behind are caught up, up to MAX_CATCH_UP_TICKS.
+     */
+    pub fn ticks_due(&mut self, now: Instant) -> u64 {
+        if now < self.next_tick {
+            return 0;
+        }
+        let behind = ((now - self.next_tick).as_nanos() / self.step.as_nanos()) as u64 + 1;
+        self.next_tick += self.step * behind as u32;
+        let run = behind.min(MAX_CATCH_UP_TICKS);
+        self.skipped_ticks += behind - run;
+        run
+    }
+    /**
+     * Records how long a tick took, returning true if it took longer than the step.
+     */
+    pub fn record_tick(&mut self, duration: Duration) -> bool {
+        let overrun = duration > self.step;
+        if overrun {
+            self.overruns += 1;
+        }
+        overrun
+    }
+    pub fn get_next_tick(&self) -> Instant {
+        self.next_tick
+    }
+    pub fn get_step(&self) -> Duration {
+        self.step
+    }
+    pub fn get_overruns(&self) -> u64 {
+        self.overruns
+    }
+    pub fn get_skipped_ticks(&self) -> u64 {
+        self.skipped_ticks
+    }
+}
+
+#[test]
+fn test_fixed_timestep() {
+    let start = Instant::now();
+    let step = DeltaTime::new(10).get_step();
+    let mut timestep = FixedTimestep::new(step, start);
+    assert_eq!(timestep.ticks_due(start), 1);
+    assert_eq!(timestep.ticks_due(start + step / 2), 0);
+    //three steps late, the missed ticks are caught up
+    assert_eq!(timestep.ticks_due(start + step * 3), 3);
+    assert_eq!(timestep.get_next_tick(), start + step * 4);
+    //far behind, only a few are run and the rest skipped
+    assert_eq!(timestep.ticks_due(start + step * 24), MAX_CATCH_UP_TICKS);
+    assert_eq!(timestep.get_skipped_ticks(), 21 - MAX_CATCH_UP_TICKS);
+    assert!(timestep.record_tick(step * 2));
+    assert!(!timestep.record_tick(step / 2));
+    assert_eq!(timestep.get_overruns(), 1);
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chunk;
+
+/**
+ * Per world gameplay rules, stored as a resource in the world.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WorldRules {
+    pub pvp: bool,
+    pub keep_inventory: bool,
+    pub spawn_point: chunk::Position,
+}
+
+impl WorldRules {
+    pub fn new() -> Self {
+        Self {
+            pvp: true,
+            keep_inventory: false,
+            spawn_point: (128, 128),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+use bevy_ecs::prelude::Entity;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::chunk::{Chunk, ChunkId};
+use crate::entity_id::EntityId;
+use crate::game_world::GameWorld;
+use crate::registry::Registry;
+use crate::world_rules::WorldRules;
+use crate::{inventory, player};
+
+/**
+ * Bumped whenever the layout of WorldSnapshot changes.
+ */
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/**
+ * A portable copy of a world. Everything is sorted by id so two snapshots of the same world can be diffed.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct WorldSnapshot {
+    pub format_version: u32,
+    pub metadata: WorldMetadata,
+    pub chunks: Vec<ChunkSnapshot>,
+    pub entities: Vec<EntitySnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct WorldMetadata {
+    pub world_name: String,
+    pub rules: WorldRules,
+    /**
+     * Every component type used by the entities, so a reader can check it knows them all before importing.
+     */
+    pub component_types: BTreeSet<String>,
+}
+
+/**
+ * A chunk serialized as cbor, the same encoding chunks are stored with.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChunkSnapshot {
+    pub chunk_id: ChunkId,
+    pub chunk_dat: Vec<u8>,
+}
+
+/**
+ * An entity's components keyed by their reflect type name.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct EntitySnapshot {
+    pub entity_id: EntityId,
+    pub components: BTreeMap<String, Value>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    UnsupportedVersion(u32),
+    Malformed(serde_json::Error),
+    InvalidChunk(ChunkId),
+    UnknownComponent(String),
+}
+
+//snapshots can be large, so only summarize them
+impl fmt::Debug for WorldSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WorldSnapshot[version:{},world:{},chunks:{},entities:{}]",
+            self.format_version,
+            self.metadata.world_name,
+            self.chunks.len(),
+            self.entities.len()
+        )
+    }
+}
+
+impl WorldSnapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Could not serialize world snapshot")
+    }
+    /**
+     * Parses a snapshot, checking the format version before the rest of the layout.
+     */
+    pub fn from_json(json: &str) -> Result<WorldSnapshot, SnapshotError> {
+        let value: Value = serde_json::from_str(json).map_err(SnapshotError::Malformed)?;
+        Self::from_value(value)
+    }
+    pub fn from_value(value: Value) -> Result<WorldSnapshot, SnapshotError> {
+        let version = value
+            .get("format_version")
+            .and_then(Value::as_u64)
+            .unwrap_or(0) as u32;
+        if version != SNAPSHOT_FORMAT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        serde_json::from_value(value).map_err(SnapshotError::Malformed)
+    }
+}
+
+/**
+ * Captures the loaded part of a world. Player characters belong to user accounts rather than the world, so they and the items they carry are left out.
+ */
+pub fn export_world(world: &mut GameWorld, registry: &Registry) -> WorldSnapshot {
+    let mut chunk_ids: Vec<ChunkId> = world.get_loaded_chunks().into_iter().copied().collect();
+    chunk_ids.sort_by_key(|c| c.id());
+    let chunks = chunk_ids
+        .into_iter()
+        .map(|chunk_id| ChunkSnapshot {
+            chunk_id: chunk_id,
+            chunk_dat: serde_cbor::to_vec(world.get_chunk_map().get(chunk_id).unwrap())
+                .expect("Could not serialize chunk as cbor"),
+        })
+        .collect();
+
+    let mut query = world
+        .get_world_mut()
+        .query::<(Entity, &EntityId, Option<&player::Player>)>();
+    let mut candidates = Vec::new();
+    let mut to_visit = Vec::new();
+    for (entity, entity_id, player) in query.iter(world.get_world()) {
+        candidates.push((entity, *entity_id));
+        if player.is_some() {
+            to_visit.push(*entity_id);
+        }
+    }
+    let mut player_owned = HashSet::new();
+    while let Some(id) = to_visit.pop() {
+        to_visit.extend(inventory::get_carried_items(world, id));
+        player_owned.insert(id);
+    }
+
+    let mut entities = Vec::new();
+    let mut component_types = BTreeSet::new();
+    for (entity, entity_id) in candidates {
+        if player_owned.contains(&entity_id) {
+            continue;
+        }
+        let components: BTreeMap<String, Value> = registry
+            .serialize_components(world.get_world(), entity)
+            .into_iter()
+            .collect();
+        component_types.extend(components.keys().cloned());
+        entities.push(EntitySnapshot {
+            entity_id: entity_id,
+            components: components,
+        });
+    }
+    entities.sort_by_key(|e| e.entity_id.id());
+
+    WorldSnapshot {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        metadata: WorldMetadata {
+            world_name: world.get_world_name().to_owned(),
+            rules: world.get_world_rules().clone(),
+            component_types: component_types,
+        },
+        chunks: chunks,
+        entities: entities,
+    }
+}
+
+/**
+ * Loads a snapshot into a world. The whole snapshot is validated first, so a bad snapshot leaves the world untouched.
+ */
+pub fn import_world(
+    world: &mut GameWorld,
+    registry: &Registry,
+    snapshot: &WorldSnapshot,
+) -> Result<(), SnapshotError> {
+    if snapshot.format_version != SNAPSHOT_FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(snapshot.format_version));
+    }
+    for type_name in snapshot.entities.iter().flat_map(|e| e.components.keys()) {
+        if !registry.has_component_type(type_name) {
+            return Err(SnapshotError::UnknownComponent(type_name.clone()));
+        }
+    }
+    let mut chunks = Vec::new();
+    for chunk in &snapshot.chunks {
+        match Chunk::new(&chunk.chunk_dat) {
+            Ok(c) => chunks.push((chunk.chunk_id, c)),
+            Err(_) => return Err(SnapshotError::InvalidChunk(chunk.chunk_id)),
+        }
+    }
+
+    world.set_world_rules(snapshot.metadata.rules.clone());
+    for chunk in chunks {
+        world.insert_chunk(chunk);
+    }
+    for entity in &snapshot.entities {
+        let mut e = world.spawn_with_entity_id(entity.entity_id);
+        for (type_name, value) in &entity.components {
+            registry.add_component_to_entity(&mut e, type_name.clone(), value.clone());
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    use crate::game_world::GameWorldBuilder;
+    use crate::registry::RegistryBuilder;
+    use crate::{chunk, health, position};
+
+    let registry = RegistryBuilder::new().build();
+    let mut world = GameWorldBuilder::new("original").build();
+    world.insert_chunk((
+        chunk::ChunkId::new_raw(3),
+        Chunk::new_from_array([[7; chunk::CHUNK_SIZE]; chunk::CHUNK_SIZE]),
+    ));
+    let mut rules = WorldRules::new();
+    rules.pvp = false;
+    world.set_world_rules(rules);
+    let rock = *world
+        .spawn()
+        .insert(health::Health::new(5))
+        .insert(position::Position {
+            pos: (1, 2),
+            load_with_chunk: true,
+        })
+        .get::<EntityId>()
+        .unwrap();
+    world.spawn().insert(player::Player {
+        username: "someone".to_owned(),
+    });
+
+    let snapshot = export_world(&mut world, &registry);
+    assert_eq!(snapshot.entities.len(), 1);
+    let parsed = WorldSnapshot::from_json(&snapshot.to_json()).unwrap();
+    assert!(parsed == snapshot);
+
+    let mut copy = GameWorldBuilder::new("copy").build();
+    import_world(&mut copy, &registry, &parsed).unwrap();
+    assert!(!copy.get_world_rules().pvp);
+    assert!(copy.is_chunk_loaded(chunk::ChunkId::new_raw(3)));
+    let e = *copy.get_uuid_map().get(rock).unwrap();
+    assert_eq!(
+        copy.get_world().get::<health::Health>(e).unwrap().current,
+        5
+    );
+    assert!(export_world(&mut copy, &registry).entities == snapshot.entities);
+
+    let outdated = snapshot
+        .to_json()
+        .replacen("\"format_version\": 1", "\"format_version\": 0", 1);
+    assert!(matches!(
+        WorldSnapshot::from_json(&outdated),
+        Err(SnapshotError::UnsupportedVersion(0))
+    ));
+}
@@ -8,6 +8,13 @@ pub enum RegistrationPolicy {
     InviteOnly,
 }
 
+#[derive(clap::ArgEnum, Clone)]
+pub enum StorageBackend {
+    Mysql,
+    Sqlite,
+    Memory,
+}
+
 #[derive(Parser)]
 #[clap(author = "Justin Suess", version, about = "rust ecs mmo server")]
 pub struct Args {
@@ -15,6 +22,19 @@ pub struct Args {
     pub port: u16,
     #[clap(long, default_value = "127.0.0.1", help = "ip to bind service to")]
     pub ip: String,
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "mysql",
+        help = "backend to persist users and worlds with"
+    )]
+    pub storage: StorageBackend,
+    #[clap(
+        long,
+        default_value = "mmo.db",
+        help = "database file to use with sqlite storage"
+    )]
+    pub sqlite_path: String,
     #[clap(
         long,
         default_value = "localhost",
@@ -39,9 +59,62 @@ pub struct Args {
         long,
         short,
         default_value = "secret",
-        help = "name of server database to use"
+        help = "secret used to sign session tokens"
     )]
     pub secret: String,
+    #[clap(
+        long,
+        default_value_t = 15,
+        help = "minutes before a session token expires and must be refreshed"
+    )]
+    pub session_lifetime_minutes: u64,
+    #[clap(
+        long,
+        default_value_t = 168,
+        help = "hours before a refresh token expires and the user must login again"
+    )]
+    pub refresh_lifetime_hours: u64,
+    #[clap(
+        long,
+        help = "apply pending database migrations and exit without starting the game"
+    )]
+    pub migrate_only: bool,
     #[clap(arg_enum, default_value = "public")]
     pub server_visibility: RegistrationPolicy,
+    #[clap(
+        long,
+        default_value_t = 3,
+        help = "number of invite codes a user can create, unless overridden by an admin"
+    )]
+    pub invite_quota: u32,
+    #[clap(
+        long,
+        default_value_t = 1,
+        help = "number of registrations a single invite code allows"
+    )]
+    pub invite_code_uses: u32,
+    #[clap(
+        long,
+        default_value_t = 168,
+        help = "hours before an invite code expires"
+    )]
+    pub invite_code_lifetime_hours: u64,
+    #[clap(
+        long,
+        default_value_t = 20,
+        help = "ticks per second each world runs at"
+    )]
+    pub tick_rate: u32,
+    #[clap(
+        long,
+        default_value = "raws",
+        help = "directory to load block and item raws from"
+    )]
+    pub raws: String,
+    #[clap(
+        long,
+        default_value_t = 4201,
+        help = "port to serve prometheus metrics on"
+    )]
+    pub metrics_port: u16,
 }
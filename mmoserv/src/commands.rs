@@ -0,0 +1,715 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bevy_ecs::entity::Entity;
+use mmolib::entity_id::EntityId;
+use mmolib::game_world::GameWorld;
+use mmolib::inventory;
+use mmolib::position;
+use mmolib::server_response_type::{ErrorCode, ServerResponseType};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::game::{self, Game};
+use crate::server_request::ServerRequest;
+
+/**
+ * Who ran a command, taken from the claims of their session token.
+ */
+pub struct CommandIssuer {
+    pub username: String,
+    pub is_admin: bool,
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    UnknownCommand(String),
+    PermissionDenied,
+    UnterminatedQuote,
+    MissingArgument(&'static str),
+    InvalidArgument { name: &'static str, value: String },
+    Failed(ErrorCode, String),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::UnknownCommand(name) => {
+                write!(f, "Unknown command /{}, try /help", name)
+            }
+            CommandError::PermissionDenied => write!(f, "Only admins can run this command"),
+            CommandError::UnterminatedQuote => write!(f, "Missing closing quote"),
+            CommandError::MissingArgument(name) => write!(f, "Missing <{}>", name),
+            CommandError::InvalidArgument { name, value } => {
+                write!(f, "\"{}\" is not a valid <{}>", value, name)
+            }
+            CommandError::Failed(_, message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl CommandError {
+    fn into_response(self, usage: Option<&str>) -> ServerResponseType {
+        let code = match &self {
+            CommandError::UnknownCommand(_) => ErrorCode::UnknownCommand,
+            CommandError::PermissionDenied => return ServerResponseType::PermissionDenied {},
+            CommandError::UnterminatedQuote
+            | CommandError::MissingArgument(_)
+            | CommandError::InvalidArgument { .. } => ErrorCode::InvalidCommandArguments,
+            CommandError::Failed(code, _) => *code,
+        };
+        let detail = match (code, usage) {
+            (ErrorCode::InvalidCommandArguments, Some(usage)) => {
+                format!("{}, usage: {}", self, usage)
+            }
+            _ => self.to_string(),
+        };
+        ServerResponseType::error_with_detail(code, &detail)
+    }
+}
+
+/**
+ * The words after the command name. Words in double quotes are kept together.
+ */
+pub struct CommandArgs {
+    args: Vec<String>,
+}
+
+impl CommandArgs {
+    /**
+     * Splits a command line, without its leading slash, into the command name and its arguments.
+     */
+    pub fn parse(line: &str) -> Result<(String, CommandArgs), CommandError> {
+        let mut args = Vec::new();
+        let mut current = String::new();
+        let mut in_word = false;
+        let mut quoted = false;
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    in_word = true;
+                }
+                c if c.is_whitespace() && !quoted => {
+                    if in_word {
+                        args.push(std::mem::take(&mut current));
+                        in_word = false;
+                    }
+                }
+                c => {
+                    current.push(c);
+                    in_word = true;
+                }
+            }
+        }
+        if quoted {
+            return Err(CommandError::UnterminatedQuote);
+        }
+        if in_word {
+            args.push(current);
+        }
+        if args.is_empty() {
+            return Err(CommandError::UnknownCommand(String::new()));
+        }
+        let name = args.remove(0).to_lowercase();
+        Ok((name, CommandArgs { args: args }))
+    }
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+    pub fn get<T: FromStr>(&self, index: usize, name: &'static str) -> Result<T, CommandError> {
+        self.get_optional(index, name)?
+            .ok_or(CommandError::MissingArgument(name))
+    }
+    pub fn get_optional<T: FromStr>(
+        &self,
+        index: usize,
+        name: &'static str,
+    ) -> Result<Option<T>, CommandError> {
+        match self.args.get(index) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| CommandError::InvalidArgument {
+                    name: name,
+                    value: value.clone(),
+                }),
+            None => Ok(None),
+        }
+    }
+    /**
+     * Every argument from index on joined back together, for free text like reasons.
+     */
+    pub fn rest(&self, index: usize) -> Option<String> {
+        if index < self.args.len() {
+            Some(self.args[index..].join(" "))
+        } else {
+            None
+        }
+    }
+}
+
+/**
+ * A chat command. Game modules add their own with Game::register_command.
+ */
+#[async_trait]
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+    /**
+     * Shown by /help and on bad arguments. Arguments in <> are required, in [] optional.
+     */
+    fn usage(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    fn admin_only(&self) -> bool {
+        false
+    }
+    /**
+     * Returns the text to show the issuer.
+     */
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError>;
+}
+
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Arc<dyn Command>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+        }
+    }
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(Arc::new(WhoCommand));
+        registry.register(Arc::new(TpCommand));
+        registry.register(Arc::new(KickCommand));
+        registry.register(Arc::new(BanCommand));
+        registry.register(Arc::new(GiveCommand));
+        registry.register(Arc::new(SaveCommand));
+        registry.register(Arc::new(TimeCommand));
+        registry.register(Arc::new(HelpCommand));
+        registry
+    }
+    pub fn register(&mut self, command: Arc<dyn Command>) {
+        self.commands.insert(command.name(), command);
+    }
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Command>> {
+        self.commands.get(name).cloned()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<dyn Command>> {
+        self.commands.values()
+    }
+}
+
+/**
+ * Runs a chat message that started with a slash. The output or error is only sent back to the issuer.
+ */
+pub async fn execute(gm: &Arc<RwLock<Game>>, req: &ServerRequest, line: &str) {
+    let issuer = match req.get_user() {
+        Some(username) => CommandIssuer {
+            username: username.to_owned(),
+            is_admin: req.is_admin(),
+        },
+        None => {
+            req.handle(ServerResponseType::error(ErrorCode::NotLoggedIn))
+                .await;
+            return;
+        }
+    };
+    req.handle(run_line(gm, &issuer, line).await).await;
+}
+
+/**
+ * Parses and runs a command line, returning what to send the issuer.
+ */
+pub async fn run_line(
+    gm: &Arc<RwLock<Game>>,
+    issuer: &CommandIssuer,
+    line: &str,
+) -> ServerResponseType {
+    let (name, args) = match CommandArgs::parse(line) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.into_response(None),
+    };
+    //cloned out so the game isn't locked while the command runs
+    let command = match gm.read().await.get_commands().get(&name) {
+        Some(command) => command,
+        None => return CommandError::UnknownCommand(name).into_response(None),
+    };
+    if command.admin_only() && !issuer.is_admin {
+        return CommandError::PermissionDenied.into_response(None);
+    }
+    if command.admin_only() {
+        info!("{} ran /{}", issuer.username, line);
+    }
+    match command.run(gm, issuer, &args).await {
+        Ok(output) => ServerResponseType::CommandOutput { output: output },
+        Err(e) => e.into_response(Some(command.usage())),
+    }
+}
+
+/**
+ * Finds the spawned character of a player connected to this world.
+ */
+fn find_player(
+    gm: &Game,
+    world: &GameWorld,
+    username: &str,
+) -> Result<(EntityId, Entity), CommandError> {
+    let player = gm
+        .get_connection(username)
+        .ok_or_else(|| {
+            CommandError::Failed(
+                ErrorCode::UserNotFound,
+                format!("{} is not in this world", username),
+            )
+        })?
+        .get_player()
+        .ok_or_else(|| {
+            CommandError::Failed(
+                ErrorCode::NotSpawned,
+                format!("{} has not spawned yet", username),
+            )
+        })?;
+    match world.get_uuid_map().get(player) {
+        Some(entity) => Ok((player, *entity)),
+        None => Err(CommandError::Failed(
+            ErrorCode::NotSpawned,
+            format!("{} is not loaded", username),
+        )),
+    }
+}
+
+/**
+ * Tells a player why they are being removed, then disconnects them from the world. Returns false if they weren't connected.
+ */
+async fn remove_player(
+    gm: &Arc<RwLock<Game>>,
+    username: &str,
+    code: ErrorCode,
+    reason: &str,
+) -> bool {
+    let connection = gm.read().await.get_connection(username).cloned();
+    match connection {
+        Some(connection) => {
+            connection
+                .send(ServerResponseType::error_with_detail(code, reason))
+                .await;
+            game::disconnect_username(gm.clone(), username.to_owned()).await;
+            true
+        }
+        None => false,
+    }
+}
+
+struct WhoCommand;
+
+#[async_trait]
+impl Command for WhoCommand {
+    fn name(&self) -> &'static str {
+        "who"
+    }
+    fn usage(&self) -> &'static str {
+        "/who"
+    }
+    fn description(&self) -> &'static str {
+        "lists the players in this world"
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let mut players = gm.read().await.get_usernames();
+        players.sort();
+        Ok(format!(
+            "{} players online: {}",
+            players.len(),
+            players.join(", ")
+        ))
+    }
+}
+
+struct TpCommand;
+
+#[async_trait]
+impl Command for TpCommand {
+    fn name(&self) -> &'static str {
+        "tp"
+    }
+    fn usage(&self) -> &'static str {
+        "/tp <x> <y> [player]"
+    }
+    fn description(&self) -> &'static str {
+        "moves you, or another player, to a position"
+    }
+    fn admin_only(&self) -> bool {
+        true
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let x: u32 = args.get(0, "x")?;
+        let y: u32 = args.get(1, "y")?;
+        let target = args
+            .get_optional::<String>(2, "player")?
+            .unwrap_or_else(|| issuer.username.clone());
+        let lk = gm.read().await;
+        let mut wlk = lk.get_world().lock().await;
+        let (_, entity) = find_player(&lk, &wlk, &target)?;
+        match wlk.get_world_mut().get_mut::<position::Position>(entity) {
+            Some(mut position) => position.pos = (x, y),
+            None => {
+                return Err(CommandError::Failed(
+                    ErrorCode::TargetNotFound,
+                    format!("{} has no position", target),
+                ))
+            }
+        }
+        Ok(format!("Teleported {} to {} {}", target, x, y))
+    }
+}
+
+struct KickCommand;
+
+#[async_trait]
+impl Command for KickCommand {
+    fn name(&self) -> &'static str {
+        "kick"
+    }
+    fn usage(&self) -> &'static str {
+        "/kick <player> [reason]"
+    }
+    fn description(&self) -> &'static str {
+        "disconnects a player from this world"
+    }
+    fn admin_only(&self) -> bool {
+        true
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let target: String = args.get(0, "player")?;
+        let reason = args
+            .rest(1)
+            .unwrap_or_else(|| format!("Kicked by {}", issuer.username));
+        if remove_player(gm, &target, ErrorCode::Kicked, &reason).await {
+            Ok(format!("Kicked {}", target))
+        } else {
+            Err(CommandError::Failed(
+                ErrorCode::UserNotFound,
+                format!("{} is not in this world", target),
+            ))
+        }
+    }
+}
+
+struct BanCommand;
+
+#[async_trait]
+impl Command for BanCommand {
+    fn name(&self) -> &'static str {
+        "ban"
+    }
+    fn usage(&self) -> &'static str {
+        "/ban <player> [reason]"
+    }
+    fn description(&self) -> &'static str {
+        "stops a user from logging in or joining any world, and disconnects them from this one"
+    }
+    fn admin_only(&self) -> bool {
+        true
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let target: String = args.get(0, "player")?;
+        let reason = args
+            .rest(1)
+            .unwrap_or_else(|| format!("Banned by {}", issuer.username));
+        let storage = gm.read().await.get_storage().clone();
+        if !storage.set_banned(&target, true).await {
+            return Err(CommandError::Failed(
+                ErrorCode::UserNotFound,
+                format!("No user named {}", target),
+            ));
+        }
+        remove_player(gm, &target, ErrorCode::Banned, &reason).await;
+        info!("{} banned {}: {}", issuer.username, target, reason);
+        Ok(format!("Banned {}", target))
+    }
+}
+
+/**
+ * The most items a single /give spawns, each item is its own entity.
+ */
+const MAX_GIVE_COUNT: u32 = 64;
+
+struct GiveCommand;
+
+#[async_trait]
+impl Command for GiveCommand {
+    fn name(&self) -> &'static str {
+        "give"
+    }
+    fn usage(&self) -> &'static str {
+        "/give <item> [count] [player]"
+    }
+    fn description(&self) -> &'static str {
+        "puts items in your inventory, or another player's"
+    }
+    fn admin_only(&self) -> bool {
+        true
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let item_name: String = args.get(0, "item")?;
+        let count: u32 = args.get_optional(1, "count")?.unwrap_or(1);
+        if count == 0 || count > MAX_GIVE_COUNT {
+            return Err(CommandError::InvalidArgument {
+                name: "count",
+                value: count.to_string(),
+            });
+        }
+        let target = args
+            .get_optional::<String>(2, "player")?
+            .unwrap_or_else(|| issuer.username.clone());
+        let lk = gm.read().await;
+        let item_type = match lk.get_registry().get_item_type(&item_name) {
+            Some(item_type) => item_type.get_id(),
+            None => {
+                return Err(CommandError::InvalidArgument {
+                    name: "item",
+                    value: item_name,
+                })
+            }
+        };
+        let mut wlk = lk.get_world().lock().await;
+        let (player, _) = find_player(&lk, &wlk, &target)?;
+        let mut dropped = 0;
+        for _ in 0..count {
+            match inventory::give_item(&mut wlk, player, item_type) {
+                Some((_, true)) => {}
+                Some((_, false)) => dropped += 1,
+                None => {
+                    return Err(CommandError::Failed(
+                        ErrorCode::TargetNotFound,
+                        format!("{} has no position", target),
+                    ))
+                }
+            }
+        }
+        if dropped > 0 {
+            Ok(format!(
+                "Gave {} {} to {}, {} didn't fit and were dropped at their feet",
+                count, item_name, target, dropped
+            ))
+        } else {
+            Ok(format!("Gave {} {} to {}", count, item_name, target))
+        }
+    }
+}
+
+struct SaveCommand;
+
+#[async_trait]
+impl Command for SaveCommand {
+    fn name(&self) -> &'static str {
+        "save"
+    }
+    fn usage(&self) -> &'static str {
+        "/save"
+    }
+    fn description(&self) -> &'static str {
+        "saves everything that changed in this world now"
+    }
+    fn admin_only(&self) -> bool {
+        true
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let lk = gm.read().await;
+        lk.get_world().lock().await.record_unsaved_changes();
+        drop(lk);
+        game::save_world_state(gm).await;
+        Ok(String::from("World saved"))
+    }
+}
+
+struct TimeCommand;
+
+#[async_trait]
+impl Command for TimeCommand {
+    fn name(&self) -> &'static str {
+        "time"
+    }
+    fn usage(&self) -> &'static str {
+        "/time"
+    }
+    fn description(&self) -> &'static str {
+        "shows the current tick of this world"
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let delta_time = gm.read().await.get_world().lock().await.get_delta_time();
+        Ok(format!(
+            "Tick {} at {} ticks per second, {:.1} seconds of game time",
+            delta_time.get_tick(),
+            (1.0 / delta_time.seconds()).round(),
+            delta_time.get_tick() as f32 * delta_time.seconds()
+        ))
+    }
+}
+
+struct HelpCommand;
+
+#[async_trait]
+impl Command for HelpCommand {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+    fn usage(&self) -> &'static str {
+        "/help [command]"
+    }
+    fn description(&self) -> &'static str {
+        "lists the commands you can run, or describes one"
+    }
+    async fn run(
+        &self,
+        gm: &Arc<RwLock<Game>>,
+        issuer: &CommandIssuer,
+        args: &CommandArgs,
+    ) -> Result<String, CommandError> {
+        let lk = gm.read().await;
+        let commands = lk.get_commands();
+        if let Some(name) = args.get_optional::<String>(0, "command")? {
+            let name = name.trim_start_matches('/').to_lowercase();
+            //admin commands are hidden from everyone else, like in the list below
+            return match commands.get(&name) {
+                Some(command) if issuer.is_admin || !command.admin_only() => {
+                    Ok(format!("{} - {}", command.usage(), command.description()))
+                }
+                _ => Err(CommandError::UnknownCommand(name)),
+            };
+        }
+        Ok(commands
+            .iter()
+            .filter(|command| issuer.is_admin || !command.admin_only())
+            .map(|command| format!("{} - {}", command.usage(), command.description()))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+
+#[cfg(test)]
+fn test_game() -> Arc<RwLock<Game>> {
+    Arc::new(RwLock::new(Game::new(
+        "../raws",
+        Arc::new(crate::memory_storage::MemoryStorage::new()),
+        String::from("commands"),
+        mmolib::timestep::DEFAULT_TICK_RATE,
+        Arc::new(crate::metrics::Metrics::new()),
+    )))
+}
+
+#[cfg(test)]
+fn error_code(response: &ServerResponseType) -> Option<ErrorCode> {
+    match response {
+        ServerResponseType::Error { code, .. } => Some(*code),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse_command_args() {
+    let (name, args) = CommandArgs::parse("Kick alice \"being rude\" again").unwrap();
+    assert_eq!(name, "kick");
+    assert_eq!(args.len(), 3);
+    assert_eq!(args.get::<String>(1, "reason").unwrap(), "being rude");
+    assert_eq!(args.rest(1).unwrap(), "being rude again");
+    assert!(args.get::<u32>(0, "count").is_err());
+    assert!(matches!(
+        CommandArgs::parse("say \"hello"),
+        Err(CommandError::UnterminatedQuote)
+    ));
+}
+
+#[tokio::test]
+async fn test_run_line() {
+    let gm = test_game();
+    let player = CommandIssuer {
+        username: String::from("alice"),
+        is_admin: false,
+    };
+    let admin = CommandIssuer {
+        username: String::from("root"),
+        is_admin: true,
+    };
+    assert!(matches!(
+        run_line(&gm, &player, "time").await,
+        ServerResponseType::CommandOutput { .. }
+    ));
+    assert!(matches!(
+        run_line(&gm, &player, "save").await,
+        ServerResponseType::PermissionDenied {}
+    ));
+    assert_eq!(
+        error_code(&run_line(&gm, &player, "nope").await),
+        Some(ErrorCode::UnknownCommand)
+    );
+    //admin commands don't exist as far as /help is concerned for everyone else
+    assert_eq!(
+        error_code(&run_line(&gm, &player, "help kick").await),
+        Some(ErrorCode::UnknownCommand)
+    );
+    assert!(matches!(
+        run_line(&gm, &admin, "help kick").await,
+        ServerResponseType::CommandOutput { .. }
+    ));
+    match run_line(&gm, &admin, "give").await {
+        ServerResponseType::Error { code, detail } => {
+            assert_eq!(code, ErrorCode::InvalidCommandArguments);
+            assert!(detail.unwrap().contains("/give <item>"));
+        }
+        _ => panic!("expected an error"),
+    }
+    assert_eq!(
+        error_code(&run_line(&gm, &admin, "give healthpotion 1000").await),
+        Some(ErrorCode::InvalidCommandArguments)
+    );
+    assert_eq!(
+        error_code(&run_line(&gm, &admin, "give healthpotion 2 alice").await),
+        Some(ErrorCode::UserNotFound)
+    );
+}
@@ -1,38 +1,147 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::{stream::SplitSink, SinkExt};
+use mmolib::codec::Codec;
 use mmolib::entity_id::EntityId;
+use mmolib::server_response_type::{ServerMessage, ServerResponseType};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 
+pub type ConnectionSink = SplitSink<WebSocketStream<TcpStream>, Message>;
+
+/**
+ * Pushes waiting to be written to one client. A client that falls this far behind is dropped instead of buffering without bound.
+ */
+const OUTBOUND_QUEUE_LENGTH: usize = 64;
+/**
+ * How long a single write may take before the client is considered gone.
+ */
+const SEND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/**
+ * Encodes a message with the connection's codec. Binary codecs are sent as binary frames.
+ */
+pub fn encode_message(codec: Codec, response: &ServerMessage) -> Message {
+    let bytes = codec.encode(response).unwrap();
+    if codec.is_binary() {
+        Message::Binary(bytes)
+    } else {
+        Message::Text(String::from_utf8(bytes).unwrap())
+    }
+}
+
+/**
+ * What the client agreed to in its hello.
+ */
+#[derive(Clone, Default, Debug)]
+pub struct ConnectionOptions {
+    pub codec: Codec,
+    pub features: Vec<String>,
+}
+
+impl ConnectionOptions {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/**
+ * The one queue everything sent to a socket goes through, replies and pushes alike, so the client receives them in the order they were queued.
+ * Created once per socket, clones share the queue and its writer.
+ */
+#[derive(Clone, Debug)]
+pub struct Outbound {
+    queue: mpsc::Sender<Message>,
+}
+
+/**
+ * Writes a socket's messages one at a time, in the order they were queued. Stops after a close frame or at the first failed or timed out write, which closes the queue.
+ */
+async fn write_outbound(mut sink: ConnectionSink, mut queue: mpsc::Receiver<Message>) {
+    while let Some(message) = queue.recv().await {
+        let closing = message.is_close();
+        match tokio::time::timeout(SEND_TIMEOUT, sink.send(message)).await {
+            Ok(Ok(_)) if !closing => {}
+            _ => break,
+        }
+    }
+}
+
+impl Outbound {
+    pub fn new(sink: ConnectionSink) -> Self {
+        let (outbound, queue) = mpsc::channel(OUTBOUND_QUEUE_LENGTH);
+        tokio::spawn(write_outbound(sink, queue));
+        Self { queue: outbound }
+    }
+    /**
+     * Queues a message behind every earlier one, waiting for room if the queue is full.
+     */
+    pub async fn send(&self, message: Message) -> bool {
+        self.queue.send(message).await.is_ok()
+    }
+    /**
+     * Queues a message without waiting. Returns false if the client is gone or too far behind.
+     */
+    pub fn try_send(&self, message: Message) -> bool {
+        self.queue.try_send(message).is_ok()
+    }
+    /**
+     * Closes the socket once everything queued before has been written.
+     */
+    pub async fn close(&self) {
+        self.send(Message::Close(None)).await;
+    }
+}
+
 #[derive(Clone)]
 pub struct Connection {
     username: String,
-    active_connection: Arc<tokio::sync::RwLock<SplitSink<WebSocketStream<TcpStream>, Message>>>,
+    options: ConnectionOptions,
     player: Option<EntityId>,
+    outbound: Outbound,
 }
 
 impl Connection {
-    pub fn new(
-        active_connection: Arc<tokio::sync::RwLock<SplitSink<WebSocketStream<TcpStream>, Message>>>,
-        username: &str,
-    ) -> Self {
+    pub fn new(outbound: Outbound, options: ConnectionOptions, username: &str) -> Self {
         Self {
             username: username.to_owned(),
-            active_connection: active_connection,
+            options: options,
             player: None,
+            outbound: outbound,
         }
     }
+    pub fn get_options(&self) -> &ConnectionOptions {
+        &self.options
+    }
     pub fn close(self) {}
-    #[inline(never)]
-    pub async fn send(
-        &self,
-        response: mmolib::server_response_type::ServerResponseType,
-    ) -> Result<(), tokio_tungstenite::tungstenite::Error> {
-        let mut lk = self.active_connection.write().await;
-        lk.send(Message::Text(serde_json::to_string(&response).unwrap()))
-            .await?;
-        Ok(())
+    /**
+     * Pushes an unsolicited message, replies to a request go through ServerRequest::handle on the same queue. Returns false once the client is gone.
+     */
+    pub async fn send(&self, response: ServerResponseType) -> bool {
+        self.send_encoded(self.encode(response)).await
+    }
+    /**
+     * Encodes a push up front, so the caller can see its size before sending it.
+     */
+    pub fn encode(&self, response: ServerResponseType) -> Message {
+        encode_message(
+            self.options.codec,
+            &ServerMessage::Push { response: response },
+        )
+    }
+    /**
+     * Queues a push behind every earlier one, waiting for room if the queue is full.
+     */
+    pub async fn send_encoded(&self, message: Message) -> bool {
+        self.outbound.send(message).await
+    }
+    /**
+     * Queues a push without waiting. Returns false if the client is gone or too far behind.
+     */
+    pub fn try_send_encoded(&self, message: Message) -> bool {
+        self.outbound.try_send(message)
     }
     pub fn get_player(&self) -> Option<EntityId> {
         self.player
@@ -44,3 +153,37 @@ impl Connection {
         self.player = Some(player);
     }
 }
+
+#[tokio::test]
+async fn test_outbound_keeps_order() {
+    use futures::StreamExt;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    let client = tokio::spawn(async move {
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut received = Vec::new();
+        while let Some(Ok(message)) = ws.next().await {
+            match message {
+                Message::Text(txt) => received.push(txt),
+                _ => break,
+            }
+        }
+        received
+    });
+    let (socket, _) = listener.accept().await.unwrap();
+    let (sink, _) = tokio_tungstenite::accept_async(socket)
+        .await
+        .unwrap()
+        .split();
+    let outbound = Outbound::new(sink);
+    //a reply and a connection's pushes share the queue
+    let connection = Connection::new(outbound.clone(), ConnectionOptions::default(), "alice");
+    assert!(outbound.send(Message::Text("reply".to_owned())).await);
+    assert!(connection.try_send_encoded(Message::Text("push".to_owned())));
+    assert!(outbound.send(Message::Text("reply2".to_owned())).await);
+    outbound.close().await;
+    assert_eq!(client.await.unwrap(), vec!["reply", "push", "reply2"]);
+    //nothing can be queued once the socket is closed
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!connection.try_send_encoded(Message::Text("late".to_owned())));
+}
@@ -1,20 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures::future::join_all;
+use mmolib::chunk;
 use mmolib::chunk::Chunk;
 use mmolib::chunk_generator;
+use mmolib::combat;
+use mmolib::delta::DeltaCache;
 use mmolib::entity_id;
 use mmolib::game_world::GameWorld;
+use mmolib::interest::ClientInterest;
+use mmolib::inventory;
+use mmolib::movement_event;
+use mmolib::protocol;
+use mmolib::server_request_type::PlayerActionType;
 use mmolib::server_response_type;
-use mmolib::server_response_type::ComponentUpdate;
-use mmolib::server_response_type::ServerResponseType;
+use mmolib::server_response_type::{BlockUpdate, ChunkPayload, ComponentUpdate};
+use mmolib::server_response_type::{ErrorCode, ServerResponseType};
+use mmolib::timestep::FixedTimestep;
 use mmolib::uuid_map;
+use mmolib::world_rules::WorldRules;
+use mmolib::world_serializer;
 use serde_json::json;
-use sqlx::MySql;
-use sqlx::Pool;
 use tokio::join;
+use tokio::sync::watch;
 use tokio::sync::Mutex;
 use tokio::sync::RwLock;
 use tokio::task;
@@ -25,54 +36,137 @@ use tracing::trace;
 use tracing::warn;
 use tracing::Level;
 
+use crate::commands::{self, Command, CommandRegistry};
 use crate::connection;
 use crate::flat_world_generator;
+use crate::loaders;
+use crate::metrics::{Metrics, PhaseTimer, TickReport};
 use crate::server;
 use crate::server_request;
 use crate::server_request::ServerRequest;
-use crate::sql_loaders;
+use crate::shutdown::{self, ShutdownPhase};
+use crate::storage::{Storage, WorldSave};
 use mmolib::game_world;
 use mmolib::raws::RawTree;
 pub struct Game {
     world: Arc<Mutex<game_world::GameWorld>>,
     chunk_generator: Box<dyn chunk_generator::ChunkGenerator>,
-    conn: Pool<MySql>,
+    storage: Arc<dyn Storage>,
     active_connections: HashMap<String, connection::Connection>,
+    interest_sets: HashMap<String, ClientInterest>,
     registry: Arc<mmolib::registry::Registry>,
+    metrics: Arc<Metrics>,
+    commands: CommandRegistry,
 }
 
 impl Game {
-    pub fn new(path: &str, conn: Pool<MySql>, world_id: String) -> Self {
+    pub fn new(
+        path: &str,
+        storage: Arc<dyn Storage>,
+        world_id: String,
+        tick_rate: u32,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         let rt = RawTree::new(path);
+        let registry = Arc::new(
+            mmolib::registry::RegistryBuilder::new()
+                .load_block_raws(&["block"], &rt)
+                .load_item_raws(&["item"], &rt)
+                .build(),
+        );
         Game {
-            conn: conn,
-            registry: Arc::new(
-                mmolib::registry::RegistryBuilder::new()
-                    .load_block_raws(&["block"], &rt)
-                    .build(),
-            ),
+            storage: storage,
+            registry: registry.clone(),
             world: Arc::new(Mutex::new(
                 game_world::GameWorldBuilder::new(&world_id)
                     .with_render_distance(10)
+                    .with_tick_rate(tick_rate)
                     .add_event::<mmolib::movement_event::MovementEvent>()
+                    .add_event::<mmolib::terrain::TerrainEvent>()
+                    .add_event::<mmolib::combat::AttackEvent>()
+                    .add_event::<mmolib::combat::DeathEvent>()
+                    .add_event::<inventory::PickupEvent>()
+                    .add_event::<inventory::DropEvent>()
+                    .add_event::<inventory::UseItemEvent>()
+                    .add_pre_update_system(mmolib::movement_event::movement_system)
+                    .add_pre_update_system(mmolib::input_ack::acknowledge_inputs_system)
+                    .add_pre_update_system(mmolib::active_effects::active_effects_tick_system)
+                    .add_pre_update_system(mmolib::combat::attack_system)
+                    .add_pre_update_system(inventory::pickup_system)
+                    .add_pre_update_system(inventory::drop_system)
+                    .add_pre_update_system(inventory::use_item_system)
+                    .add_post_update_system(mmolib::active_effects::apply_terrain_effects)
+                    .add_post_update_system(mmolib::combat::fall_damage_system)
+                    .add_post_update_system(mmolib::combat::death_system)
+                    .add_post_update_system(mmolib::respawn::respawn_system)
+                    .with_registry(registry)
                     .with_raws(rt)
                     .build(),
             )),
             active_connections: HashMap::new(),
+            interest_sets: HashMap::new(),
             chunk_generator: Box::new(flat_world_generator::FlatWorldGenerator::new()),
+            metrics: metrics,
+            commands: CommandRegistry::with_builtins(),
         }
     }
+    pub fn get_world(&self) -> &Arc<Mutex<game_world::GameWorld>> {
+        &self.world
+    }
+    pub fn get_storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+    pub fn get_registry(&self) -> &Arc<mmolib::registry::Registry> {
+        &self.registry
+    }
+    pub fn get_connection(&self, username: &str) -> Option<&connection::Connection> {
+        self.active_connections.get(username)
+    }
+    pub fn get_usernames(&self) -> Vec<String> {
+        self.active_connections.keys().cloned().collect()
+    }
+    pub fn get_commands(&self) -> &CommandRegistry {
+        &self.commands
+    }
+    /**
+     * Adds a chat command to this world, replacing any command with the same name.
+     */
+    pub fn register_command(&mut self, command: Arc<dyn Command>) {
+        self.commands.register(command);
+    }
     pub async fn handle(gm: Arc<RwLock<Self>>, req: ServerRequest) {
         match &req.get_dat() {
             mmolib::server_request_type::ServerRequestType::Join { world_name } => {
                 match req.get_user() {
                     Some(username) => {
-                        info!("Player {} has joined the game", username.to_owned());
-                        gm.write()
+                        let banned = gm
+                            .read()
                             .await
-                            .active_connections
-                            .insert(username.to_owned(), req.get_connection());
-                        req.handle(&ServerResponseType::Ok {}).await;
+                            .storage
+                            .get_user(username)
+                            .await
+                            .map_or(false, |user| user.banned);
+                        if banned {
+                            req.handle(ServerResponseType::error(ErrorCode::Banned))
+                                .await;
+                            return;
+                        }
+                        info!("Player {} has joined the game", username.to_owned());
+                        let mut lk = gm.write().await;
+                        let connection = req.get_connection();
+                        //a new connection knows nothing, so everything in range is sent in full again
+                        let mut interest = ClientInterest::default();
+                        if connection
+                            .get_options()
+                            .has_feature(protocol::FEATURE_DELTA_COMPRESSION)
+                        {
+                            interest.deltas = Some(DeltaCache::new());
+                        }
+                        lk.active_connections
+                            .insert(username.to_owned(), connection);
+                        lk.interest_sets.insert(username.to_owned(), interest);
+                        drop(lk);
+                        req.handle(ServerResponseType::Ok {}).await;
                     }
                     None => {}
                 }
@@ -82,6 +176,10 @@ impl Game {
                 world_name,
                 message,
             } => {
+                if let Some(line) = message.strip_prefix('/') {
+                    commands::execute(&gm, &req, line).await;
+                    return;
+                }
                 for (username, connection) in &gm.read().await.active_connections {
                     connection
                         .send(ServerResponseType::ChatMessage {
@@ -98,13 +196,7 @@ impl Game {
                 Some(user) => {
                     let mut lk = gm.write().await;
                     let mut wlk = lk.world.lock().await;
-                    match sql_loaders::check_if_player_exists_in_world(
-                        lk.conn.clone(),
-                        &wlk.get_world_name(),
-                        user,
-                    )
-                    .await
-                    {
+                    match lk.storage.find_player(&wlk.get_world_name(), user).await {
                         Some(entity_id) => {
                             if lk.active_connections.contains_key(user)
                                 && lk
@@ -114,8 +206,8 @@ impl Game {
                                     .get_player()
                                     .is_none()
                             {
-                                sql_loaders::load_entity(
-                                    lk.conn.clone(),
+                                loaders::load_entity(
+                                    &*lk.storage,
                                     entity_id,
                                     &mut *wlk,
                                     &lk.registry,
@@ -126,16 +218,16 @@ impl Game {
                                     .get_mut(user)
                                     .unwrap()
                                     .set_player(entity_id);
+                                drop(lk);
                                 info!("Player {} has loaded a character", user);
+                                req.handle(ServerResponseType::Ok {}).await;
                             } else {
                                 info!(
                                     "Player {} tried to spawn character without Join-ing game",
                                     user
                                 );
-                                req.handle(&ServerResponseType::Error {
-                                    message: "Tried to spawn character in a game without joining",
-                                })
-                                .await;
+                                req.handle(ServerResponseType::error(ErrorCode::NotJoined))
+                                    .await;
                             }
                         }
                         None => {
@@ -151,26 +243,28 @@ impl Game {
                                 drop(lk);
                                 info!("Player {} has created a new character", user);
                                 let eid = spawn_or_load_player(&gm.clone(), user).await;
-                                let conn = gm.read().await.conn.clone();
                                 let lk = gm.read().await;
                                 let mut wlk = lk.world.lock().await;
-                                sql_loaders::save_entity(
-                                    conn.clone(),
-                                    eid,
-                                    &mut *wlk,
-                                    &lk.registry,
-                                )
-                                .await;
-                                sql_loaders::add_player_to_user(conn, user, eid).await;
+                                loaders::save_entity(&*lk.storage, eid, &mut *wlk, &lk.registry)
+                                    .await;
+                                lk.storage.add_player_to_user(user, eid).await;
+                                drop(wlk);
+                                drop(lk);
+                                req.handle(ServerResponseType::Ok {}).await;
+                            } else {
+                                info!(
+                                    "Player {} tried to create a character without Join-ing game",
+                                    user
+                                );
+                                req.handle(ServerResponseType::error(ErrorCode::NotJoined))
+                                    .await;
                             }
                         }
                     }
                 }
                 None => {
-                    req.handle(&ServerResponseType::Error {
-                        message: "You are not logged in",
-                    })
-                    .await;
+                    req.handle(ServerResponseType::error(ErrorCode::NotLoggedIn))
+                        .await;
                 }
             },
             mmolib::server_request_type::ServerRequestType::PlayerList { world_name } => {
@@ -179,37 +273,289 @@ impl Game {
                 for (username, connection) in &lk.active_connections {
                     players.push(username.clone());
                 }
-                req.handle(&ServerResponseType::PlayerList { players })
-                    .await;
+                req.handle(ServerResponseType::PlayerList { players }).await;
+            }
+            mmolib::server_request_type::ServerRequestType::SetPvp {
+                world_name,
+                enabled,
+            } => {
+                let enabled = *enabled;
+                update_world_rules(&gm, req, move |rules| rules.pvp = enabled).await;
+            }
+            mmolib::server_request_type::ServerRequestType::SetKeepInventory {
+                world_name,
+                enabled,
+            } => {
+                let enabled = *enabled;
+                update_world_rules(&gm, req, move |rules| rules.keep_inventory = enabled).await;
+            }
+            mmolib::server_request_type::ServerRequestType::SetSpawnPoint {
+                world_name,
+                position,
+            } => {
+                let position = *position;
+                update_world_rules(&gm, req, move |rules| rules.spawn_point = position).await;
+            }
+            mmolib::server_request_type::ServerRequestType::PlayerAction {
+                world_name,
+                action,
+                input_sequence,
+            } => {
+                handle_player_action(&gm, &req, action, *input_sequence).await;
+            }
+            mmolib::server_request_type::ServerRequestType::ExportWorld { world_name } => {
+                if req.is_admin() {
+                    let snapshot = export_world(&gm).await;
+                    req.handle(ServerResponseType::WorldSnapshot { snapshot: snapshot })
+                        .await;
+                } else {
+                    req.handle(ServerResponseType::PermissionDenied {}).await;
+                }
             }
             _ => {
-                warn!("Request sent to game was not handled")
+                warn!("Request sent to game was not handled");
+                req.handle(ServerResponseType::error(ErrorCode::UnsupportedRequest))
+                    .await;
             }
         }
     }
 
-    pub async fn start_game(gm: Arc<RwLock<Self>>) {
-        let mut counter = 0;
+    /**
+     * Loads a snapshot into the world, must be called before the game is started.
+     */
+    pub async fn import_snapshot(
+        &self,
+        snapshot: &world_serializer::WorldSnapshot,
+    ) -> Result<(), world_serializer::SnapshotError> {
+        let mut wlk = self.world.lock().await;
+        world_serializer::import_world(&mut wlk, &self.registry, snapshot)?;
+        //index the imported positions, otherwise chunks unloaded on the first tick would leave their entities behind
+        wlk.run_between_ticks_scheduler();
+        Ok(())
+    }
+
+    /**
+     * Ticks the world until the server starts draining, then saves it. The returned task finishes once the world is saved.
+     */
+    pub async fn start_game(
+        gm: Arc<RwLock<Self>>,
+        mut shutdown: watch::Receiver<ShutdownPhase>,
+    ) -> task::JoinHandle<()> {
+        let mut counter: u64 = 0;
         task::spawn(async move {
             load_world_state(&gm).await;
-            loop {
-                //retrieve list of chunks close to player and load them
-                load_and_unload_chunks(&gm).await;
-                run_pre_update_scheduler(&gm).await;
-                run_between_ticks_scheduler(&gm).await;
-                run_event_updater(&gm).await;
-                run_post_update_scheduler(&gm).await;
-                if counter % 5 == 0 {
-                    save_world_state(&gm).await;
-                } //save every 25 ticks
-                send_ticked_messages(&gm).await;
-                join!(clear_trackers(&gm), delete_scheduled_entities(&gm));
-                //tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                trace!("Tick number {}", counter);
-                std::io::stdout().flush();
-                counter += 1;
+            let step = gm
+                .read()
+                .await
+                .world
+                .lock()
+                .await
+                .get_delta_time()
+                .get_step();
+            let mut timestep = FixedTimestep::new(step, Instant::now());
+            'ticking: loop {
+                let due = timestep.ticks_due(Instant::now());
+                for _ in 0..due {
+                    //the tick in progress always finishes, no new one starts once draining
+                    if *shutdown.borrow() != ShutdownPhase::Running {
+                        break 'ticking;
+                    }
+                    let started = Instant::now();
+                    let mut report = tick(&gm, counter).await;
+                    let elapsed = started.elapsed();
+                    let overran = timestep.record_tick(elapsed);
+                    report.duration = elapsed;
+                    report.overruns = timestep.get_overruns();
+                    report.skipped_ticks = timestep.get_skipped_ticks();
+                    record_tick_metrics(&gm, report).await;
+                    if overran {
+                        warn!(
+                            "Tick {} took {:?}, longer than the {:?} step ({} overruns, {} ticks skipped)",
+                            counter,
+                            elapsed,
+                            step,
+                            timestep.get_overruns(),
+                            timestep.get_skipped_ticks()
+                        );
+                    }
+                    counter += 1;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep_until(timestep.get_next_tick().into()) => {}
+                    _ = shutdown::wait_for_phase(&mut shutdown, ShutdownPhase::Draining) => {}
+                }
             }
-        });
+            save_world_for_shutdown(&gm).await;
+        })
+    }
+}
+
+async fn tick(gm: &Arc<RwLock<Game>>, counter: u64) -> TickReport {
+    gm.read().await.world.lock().await.set_tick(counter);
+    let mut timer = PhaseTimer::new();
+    //retrieve list of chunks close to player and load them
+    load_and_unload_chunks(gm).await;
+    timer.mark("load_and_unload_chunks");
+    run_pre_update_scheduler(gm).await;
+    timer.mark("pre_update_scheduler");
+    run_between_ticks_scheduler(gm).await;
+    timer.mark("between_ticks_scheduler");
+    run_event_updater(gm).await;
+    timer.mark("event_updater");
+    run_post_update_scheduler(gm).await;
+    timer.mark("post_update_scheduler");
+    record_unsaved_changes(gm).await;
+    timer.mark("record_unsaved_changes");
+    if counter % 5 == 0 {
+        save_world_state(gm).await;
+        timer.mark("save_world_state");
+    } //save every 5 ticks
+    let bytes_sent = send_ticked_messages(gm, counter).await;
+    timer.mark("send_ticked_messages");
+    clear_trackers(gm).await;
+    timer.mark("clear_trackers");
+    delete_scheduled_entities(gm).await;
+    timer.mark("delete_scheduled_entities");
+    trace!("Tick number {}", counter);
+    std::io::stdout().flush();
+    let lk = gm.read().await;
+    let wlk = lk.world.lock().await;
+    TickReport {
+        tick: counter,
+        duration: Duration::ZERO,
+        phases: timer.into_phases(),
+        entities: wlk.get_world().entities().len() as usize,
+        chunks: wlk.get_loaded_chunks().len(),
+        players: lk.active_connections.len(),
+        bytes_sent: bytes_sent,
+        overruns: 0,
+        skipped_ticks: 0,
+    }
+}
+
+async fn record_tick_metrics(gm: &Arc<RwLock<Game>>, report: TickReport) {
+    let lk = gm.read().await;
+    let world_name = lk.world.lock().await.get_world_name().to_owned();
+    lk.metrics.record_tick(&world_name, report);
+}
+
+async fn update_world_rules(
+    gm: &Arc<RwLock<Game>>,
+    req: ServerRequest,
+    update: impl FnOnce(&mut WorldRules),
+) {
+    if !req.is_admin() {
+        req.handle(ServerResponseType::PermissionDenied {}).await;
+        return;
+    }
+    let lk = gm.read().await;
+    let mut wlk = lk.world.lock().await;
+    let mut rules = wlk.get_world_rules().clone();
+    update(&mut rules);
+    lk.storage
+        .save_world_rules(wlk.get_world_name(), &rules)
+        .await;
+    wlk.set_world_rules(rules);
+    drop(wlk);
+    drop(lk);
+    req.handle(ServerResponseType::Ok {}).await;
+}
+
+async fn handle_player_action(
+    gm: &Arc<RwLock<Game>>,
+    req: &ServerRequest,
+    action: &PlayerActionType,
+    input_sequence: Option<u64>,
+) {
+    let lk = gm.read().await;
+    let player = req
+        .get_user()
+        .and_then(|user| lk.active_connections.get(user))
+        .and_then(|connection| connection.get_player());
+    let player = match player {
+        Some(player) => player,
+        None => {
+            req.handle(ServerResponseType::error(ErrorCode::NotSpawned))
+                .await;
+            return;
+        }
+    };
+    let mut wlk = lk.world.lock().await;
+    let entity = match wlk.get_uuid_map().get(player) {
+        Some(entity) => *entity,
+        None => {
+            warn!("Player entity {} is not loaded in the world", player);
+            drop(wlk);
+            req.handle(ServerResponseType::error(ErrorCode::NotSpawned))
+                .await;
+            return;
+        }
+    };
+    if let Some(sequence) = input_sequence {
+        wlk.get_input_acks_mut().record(player, sequence);
+    }
+    match action {
+        PlayerActionType::Move(direction) => {
+            wlk.send_event(movement_event::MovementEvent::new(entity, *direction));
+        }
+        PlayerActionType::Attack(target) => match wlk.get_uuid_map().get(*target).map(|x| *x) {
+            Some(target) => {
+                wlk.send_event(combat::AttackEvent::new(entity, target));
+            }
+            None => {
+                req.handle(ServerResponseType::error_with_detail(
+                    ErrorCode::TargetNotFound,
+                    "Attack target does not exist",
+                ))
+                .await;
+            }
+        },
+        PlayerActionType::Pickup(item) | PlayerActionType::Drop(item) => {
+            match wlk.get_uuid_map().get(*item).map(|x| *x) {
+                Some(item) => {
+                    if let PlayerActionType::Pickup(_) = action {
+                        wlk.send_event(inventory::PickupEvent {
+                            actor: entity,
+                            item: item,
+                        });
+                    } else {
+                        wlk.send_event(inventory::DropEvent {
+                            actor: entity,
+                            item: item,
+                        });
+                    }
+                }
+                None => {
+                    req.handle(ServerResponseType::error_with_detail(
+                        ErrorCode::TargetNotFound,
+                        "Item does not exist",
+                    ))
+                    .await;
+                }
+            }
+        }
+        PlayerActionType::UseOn { item, target } => {
+            let uuid_map = wlk.get_uuid_map();
+            match (
+                uuid_map.get(*item).map(|x| *x),
+                uuid_map.get(*target).map(|x| *x),
+            ) {
+                (Some(item), Some(target)) => {
+                    wlk.send_event(inventory::UseItemEvent {
+                        actor: entity,
+                        item: item,
+                        target: target,
+                    });
+                }
+                _ => {
+                    req.handle(ServerResponseType::error_with_detail(
+                        ErrorCode::TargetNotFound,
+                        "Item or target does not exist",
+                    ))
+                    .await;
+                }
+            }
+        }
     }
 }
 
@@ -217,69 +563,126 @@ async fn delete_scheduled_entities(gm: &Arc<RwLock<Game>>) {
     let lk = gm.read().await;
     let mut wlk = lk.world.lock().await;
     for ent in wlk.get_entities_scheduled_for_deletion() {
-        sql_loaders::delete_entity(lk.conn.clone(), ent);
+        lk.storage.delete_entity(ent).await;
         wlk.despawn_entity_by_entity_id(ent);
     }
 }
-async fn send_ticked_messages(gm: &Arc<RwLock<Game>>) {
-    let lk = gm.read().await;
+/**
+ * Sends every player what changed around them and returns how many bytes that took.
+ */
+async fn send_ticked_messages(gm: &Arc<RwLock<Game>>, tick: u64) -> u64 {
+    let mut bytes_sent = 0;
+    let mut disconnected = Vec::new();
+    let mut guard = gm.write().await;
+    let lk = &mut *guard;
     let mut wlk = lk.world.lock().await;
-    let component_changes = gm
-        .read()
-        .await
+    let component_changes = lk
         .registry
         .get_network_change_serialization(wlk.get_world_mut());
-    let gmcl = gm.clone();
-    for (id, player, position) in wlk
+    let players: Vec<(String, chunk::Position)> = wlk
         .get_world_mut()
-        .query::<(
-            &mmolib::entity_id::EntityId,
-            &mmolib::player::Player,
-            &mmolib::position::Position,
-        )>()
+        .query::<(&mmolib::player::Player, &mmolib::position::Position)>()
         .iter(wlk.get_world())
-    {
-        let mut component_updates = Vec::new();
+        .map(|(player, position)| (player.username.clone(), position.pos))
+        .collect();
+    for (username, position) in players {
+        let viewer = lk
+            .active_connections
+            .get(&username)
+            .and_then(|connection| connection.get_player());
         let chunks = game_world::GameWorld::get_chunks_in_radius_of_position(
             wlk.get_render_distance(),
-            position.pos,
+            position,
         );
-        for c in chunks {
-            let ents = wlk.get_entities_in_chunk(c);
-            for e in ents {
-                if let Some(change) = component_changes.get(&e) {
-                    for up in change {
-                        component_updates.push(up.clone());
-                    }
-                }
+        let visible_chunks: HashSet<chunk::ChunkId> = chunks
+            .into_iter()
+            .filter(|c| wlk.is_chunk_loaded(*c))
+            .collect();
+        let mut visible = HashSet::new();
+        for c in &visible_chunks {
+            visible.extend(wlk.get_entities_in_chunk(*c));
+        }
+        //carried items have no position, so the owner is sent them through its inventory
+        if let Some(viewer) = viewer {
+            visible.extend(inventory::get_carried_items(&wlk, viewer));
+        }
+        let interest = match lk.interest_sets.get_mut(&username) {
+            Some(interest) => interest,
+            None => {
+                tracing::warn!(
+                    "Player not found in active connections when attempting to send ticked message"
+                );
+                continue;
+            }
+        };
+        let change = interest.entities.update(visible);
+        let chunk_change = interest.chunks.update(visible_chunks);
+        let mut component_updates = Vec::new();
+        //entities new to the client get every component, ones it already knows only get what changed
+        for e in change.entered {
+            if let Some(entity) = wlk.get_uuid_map().get(e) {
+                component_updates.extend(lk.registry.get_network_snapshot(
+                    wlk.get_world(),
+                    *entity,
+                    e,
+                ));
+            }
+        }
+        for e in change.stayed {
+            if let Some(change) = component_changes.get(&e) {
+                component_updates.extend(change.iter().cloned());
+            }
+        }
+        component_updates.retain(|update| lk.registry.is_visible_to(update, viewer));
+        if let Some(deltas) = &mut interest.deltas {
+            for e in &change.left {
+                deltas.forget_entity(*e);
+            }
+            component_updates = component_updates
+                .into_iter()
+                .filter_map(|update| deltas.compress(update))
+                .collect();
+        }
+        //same for chunks, the whole chunk once and then only the blocks that changed
+        let chunk_map = wlk.get_chunk_map();
+        let loaded_chunks = chunk_change
+            .entered
+            .into_iter()
+            .filter_map(|c| {
+                chunk_map.get(c).map(|chunk| ChunkPayload {
+                    chunk_id: c,
+                    chunk: chunk.clone(),
+                })
+            })
+            .collect();
+        let mut block_updates = Vec::new();
+        for c in chunk_change.stayed {
+            for (position, block) in chunk_map.get_block_changes(c) {
+                block_updates.push(BlockUpdate {
+                    block_pos: position,
+                    block_type_id: block,
+                });
             }
         }
         let response = server_response_type::ServerResponseType::Ticked {
             world_name: wlk.get_world_name().to_owned(),
+            tick: tick,
+            last_processed_input: viewer.and_then(|id| wlk.get_input_acks().get_processed(id)),
             component_updates: component_updates,
-            block_updates: Vec::new(),
+            block_updates: block_updates,
+            removed_entities: change.left,
+            loaded_chunks: loaded_chunks,
+            unloaded_chunks: chunk_change.left,
         };
-        match lk.active_connections.get(&player.username) {
+        match lk.active_connections.get(&username) {
             Some(connection) => {
-                let conn = connection.clone();
-                let username = player.username.clone();
-                let gmcl = gm.clone();
-                tokio::task::spawn(async move {
-                    match tokio::time::timeout(
-                        std::time::Duration::from_secs(3),
-                        conn.send(response),
-                    )
-                    .await
-                    {
-                        Ok(Ok(_)) => {
-                            tracing::trace!("Sent update to player {}", username);
-                        }
-                        Err(_) | Ok(Err(_)) => {
-                            tracing::info!("Player {} disconnected", username);
-                            disconnect_username(gmcl, username).await;
-                        }
-                    }
-                });
+                let message = connection.encode(response);
+                bytes_sent += message.len() as u64;
+                //queued behind the previous ticks, a client that can't keep up is dropped
+                if !connection.try_send_encoded(message) {
+                    tracing::info!("Player {} disconnected", username);
+                    disconnected.push(username);
+                }
             }
             None => {
                 tracing::warn!(
@@ -288,16 +691,26 @@ async fn send_ticked_messages(gm: &Arc<RwLock<Game>>) {
             }
         }
     }
+    drop(wlk);
+    drop(guard);
+    for username in disconnected {
+        disconnect_username(gm.clone(), username).await;
+    }
+    bytes_sent
 }
 //remove timed out connections
 
-async fn disconnect_username(gmcl: Arc<RwLock<Game>>, username: String) {
+pub async fn disconnect_username(gmcl: Arc<RwLock<Game>>, username: String) {
     let mut lk = gmcl.write().await;
     match lk.active_connections.get(&username) {
         Some(conn) => match conn.get_player() {
             Some(id) => {
                 let mut wlk = lk.world.lock().await;
-                wlk.despawn_entity_by_entity_id(id);
+                wlk.get_input_acks_mut().remove(id);
+                //the player and its items are saved in full before they leave the world, so nothing done this tick is lost
+                wlk.record_unsaved_changes();
+                loaders::save_entity(&*lk.storage, id, &wlk, &lk.registry).await;
+                inventory::despawn_with_carried_items(&mut wlk, id);
             }
             None => {}
         },
@@ -309,29 +722,32 @@ async fn disconnect_username(gmcl: Arc<RwLock<Game>>, username: String) {
         }
     }
     lk.active_connections.remove(&username);
+    lk.interest_sets.remove(&username);
 }
 async fn spawn_or_load_player(gm: &Arc<RwLock<Game>>, username: &str) -> entity_id::EntityId {
     let mut lk = gm.write().await;
     let nm = lk.world.lock().await.get_world_name().to_owned();
-    if let Some(id) =
-        sql_loaders::check_if_player_exists_in_world(lk.conn.clone(), &nm, username).await
-    {
+    if let Some(id) = lk.storage.find_player(&nm, username).await {
         let mut wlk = lk.world.lock().await;
         tracing::info!("Loading existing player {} {}", id, username);
-        sql_loaders::load_entity(lk.conn.clone(), id, &mut wlk, &lk.registry);
+        loaders::load_entity(&*lk.storage, id, &mut wlk, &lk.registry).await;
         id
     } else {
         let mut wlk = lk.world.lock().await;
+        let spawn_point = wlk.get_world_rules().spawn_point;
         let mut e = wlk.spawn();
         tracing::info!("Creating new player from username {}", username);
         e.insert(mmolib::player::Player {
             username: username.to_owned(),
-            last_ping_timestamp: 0,
         })
+        .insert(mmolib::player::LastPing::default())
         .insert(mmolib::position::Position {
-            pos: (128, 128),
+            pos: spawn_point,
             load_with_chunk: false,
-        });
+        })
+        .insert(mmolib::health::Health::new(100))
+        .insert(mmolib::combat::CombatStats::new(10, 0, 1))
+        .insert(inventory::Inventory::new(20));
         let id = *e.get::<entity_id::EntityId>().unwrap();
         drop(wlk);
         let mut conn = lk.active_connections.get_mut(username).unwrap();
@@ -342,8 +758,12 @@ async fn spawn_or_load_player(gm: &Arc<RwLock<Game>>, username: &str) -> entity_
 
 async fn load_world_state(gm: &Arc<RwLock<Game>>) {
     let mut lk = gm.read().await;
-    let chunks = sql_loaders::retreive_all_loaded_chunks_and_entities(
-        &lk.conn.clone(),
+    let mut wlk = lk.world.lock().await;
+    let rules = loaders::load_world_rules(&*lk.storage, wlk.get_world_name()).await;
+    wlk.set_world_rules(rules);
+    drop(wlk);
+    let chunks = loaders::retreive_all_loaded_chunks_and_entities(
+        &*lk.storage,
         &mut *lk.world.lock().await,
         &*(lk.registry),
     )
@@ -355,23 +775,61 @@ async fn load_world_state(gm: &Arc<RwLock<Game>>) {
     }
 }
 
-async fn save_world_state(gm: &Arc<RwLock<Game>>) {
-    info!("Saving world state");
+/**
+ * Exports the whole world, not just the part near players. Chunks that were unloaded to storage are loaded back in first, and unloaded again on the next tick.
+ */
+pub async fn export_world(gm: &Arc<RwLock<Game>>) -> world_serializer::WorldSnapshot {
+    let lk = gm.read().await;
+    let mut wlk = lk.world.lock().await;
+    let world_name = wlk.get_world_name().to_owned();
+    for chunk_id in lk.storage.chunk_ids(&world_name).await {
+        if wlk.is_chunk_loaded(chunk_id) {
+            continue;
+        }
+        match loaders::load_chunk_and_entities(&*lk.storage, chunk_id, &mut *wlk, &lk.registry)
+            .await
+        {
+            Some(chunk) => wlk.insert_chunk((chunk_id, chunk)),
+            None => tracing::error!("Could not load chunk for export"),
+        }
+    }
+    //index the loaded positions, otherwise unloading the chunks again would leave their entities behind
+    wlk.run_between_ticks_scheduler();
+    world_serializer::export_world(&mut *wlk, &lk.registry)
+}
+pub async fn save_world_state(gm: &Arc<RwLock<Game>>) {
     let mut lk = gm.read().await;
     let mut wlk = lk.world.lock().await;
-    let chunks: Vec<mmolib::chunk::ChunkId> = wlk
-        .get_chunk_map()
-        .get_loaded_chunks()
-        .iter()
-        .map(|x| **x)
-        .collect();
-    for c in chunks {
-        let chunk = wlk.get_chunk_map().get(c).unwrap();
-        sql_loaders::save_chunk(lk.conn.clone(), chunk, c, &*wlk, true).await;
-        for entity in wlk.get_entities_in_chunk(c) {
-            sql_loaders::save_entity(lk.conn.clone(), entity, &mut *wlk, &lk.registry).await;
+    loaders::save_unsaved_changes(&*lk.storage, &mut *wlk, &lk.registry).await;
+}
+/**
+ * Saves what changed since the last save, then every loaded chunk and its entities marked as unloaded, so the next start only loads chunks players are near.
+ */
+async fn save_world_for_shutdown(gm: &Arc<RwLock<Game>>) {
+    record_unsaved_changes(gm).await;
+    save_world_state(gm).await;
+    let lk = gm.read().await;
+    let wlk = lk.world.lock().await;
+    let mut save = WorldSave::default();
+    for chunk_id in wlk.get_loaded_chunks() {
+        for ent in wlk.get_entities_in_chunk(*chunk_id) {
+            loaders::add_entity(&mut save, &wlk, ent, &*lk.registry);
+        }
+        if let Some(chunk) = wlk.get_chunk_map().get(*chunk_id) {
+            loaders::add_chunk(&mut save, *chunk_id, chunk, false);
         }
     }
+    info!(
+        "Saving {} chunks of {} as unloaded",
+        save.chunks.len(),
+        wlk.get_world_name()
+    );
+    lk.storage.save_world(wlk.get_world_name(), &save).await;
+}
+async fn record_unsaved_changes(gm: &Arc<RwLock<Game>>) {
+    let lk = gm.read().await;
+    let mut wlk = lk.world.lock().await;
+    wlk.record_unsaved_changes();
 }
 async fn clear_trackers(gm: &Arc<RwLock<Game>>) {
     let lk = gm.read().await;
@@ -409,9 +867,8 @@ async fn load_and_unload_chunks(gm: &Arc<RwLock<Game>>) {
     drop(lk);
     for chunk_id in chunks {
         let mut lk = gm.read().await;
-        let chunk_in_db =
-            sql_loaders::check_if_chunk_exists(&lk.conn.clone(), chunk_id, &*lk.world.lock().await)
-                .await;
+        let world_name = lk.world.lock().await.get_world_name().to_owned();
+        let chunk_in_db = lk.storage.chunk_exists(&world_name, chunk_id).await;
         let chunk_in_game = lk.world.lock().await.is_chunk_loaded(chunk_id);
         let chunk_should_be_in_game = chunks_that_should_be_loaded.contains(&chunk_id);
         drop(lk);
@@ -423,10 +880,13 @@ async fn load_and_unload_chunks(gm: &Arc<RwLock<Game>>) {
                 //load the chunk
                 let mut lk = gm.read().await;
                 let mut wlk = lk.world.lock().await;
-                let conn = &lk.conn.clone();
-                let chunk =
-                    sql_loaders::load_chunk_and_entities(conn, chunk_id, &mut *wlk, &*lk.registry)
-                        .await;
+                let chunk = loaders::load_chunk_and_entities(
+                    &*lk.storage,
+                    chunk_id,
+                    &mut *wlk,
+                    &*lk.registry,
+                )
+                .await;
                 match chunk {
                     Some(chunk) => {
                         wlk.insert_chunk((chunk_id, chunk));
@@ -450,13 +910,15 @@ async fn load_and_unload_chunks(gm: &Arc<RwLock<Game>>) {
             (_, true, false) => {
                 let mut lk = gm.read().await;
                 let mut wlk = lk.world.lock().await;
+                let mut save = WorldSave::default();
                 let ents = wlk.get_entities_in_chunk(chunk_id);
                 for ent in ents {
-                    sql_loaders::save_entity(lk.conn.clone(), ent, &mut *wlk, &*lk.registry).await;
-                    wlk.remove_entity(ent);
+                    loaders::add_entity(&mut save, &wlk, ent, &*lk.registry);
+                    inventory::despawn_with_carried_items(&mut wlk, ent);
                 }
                 let chk = wlk.unload_chunk(chunk_id).unwrap();
-                sql_loaders::save_chunk(lk.conn.clone(), &chk, chunk_id, &mut *wlk, false).await;
+                loaders::add_chunk(&mut save, chunk_id, &chk, false);
+                lk.storage.save_world(wlk.get_world_name(), &save).await;
             }
             (_, false, false) => {
                 //do nothing
@@ -464,3 +926,33 @@ async fn load_and_unload_chunks(gm: &Arc<RwLock<Game>>) {
         }
     }
 }
+
+#[tokio::test]
+async fn test_export_world_includes_unloaded_chunks() {
+    let gm = Arc::new(RwLock::new(Game::new(
+        "../raws",
+        Arc::new(crate::memory_storage::MemoryStorage::new()),
+        String::from("export"),
+        mmolib::timestep::DEFAULT_TICK_RATE,
+        Arc::new(Metrics::new()),
+    )));
+    let chunk_id = chunk::chunk_id_from_position((3, 3));
+    {
+        let lk = gm.read().await;
+        let chunk = lk.chunk_generator.generate_chunk(chunk_id, &*lk.registry);
+        let mut wlk = lk.world.lock().await;
+        wlk.insert_chunk((chunk_id, chunk));
+        wlk.spawn().insert(mmolib::position::Position {
+            pos: (3, 3),
+            load_with_chunk: true,
+        });
+        wlk.run_between_ticks_scheduler();
+    }
+    //nobody is online, so the chunk and its entity are unloaded to storage
+    load_and_unload_chunks(&gm).await;
+    assert!(!gm.read().await.world.lock().await.is_chunk_loaded(chunk_id));
+    let snapshot = export_world(&gm).await;
+    assert_eq!(snapshot.chunks.len(), 1);
+    assert_eq!(snapshot.chunks[0].chunk_id, chunk_id);
+    assert_eq!(snapshot.entities.len(), 1);
+}
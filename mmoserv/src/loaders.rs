@@ -0,0 +1,224 @@
+use std::{any::Any, collections::HashSet, sync::Arc};
+
+use bevy_ecs::world::EntityMut;
+use mmolib::{
+    chunk::{self, Chunk, ChunkId},
+    component,
+    entity_id::{self, EntityId},
+    game_world::{self, GameWorld},
+    hashing, inventory,
+    raws::RawTree,
+    registry::Registry,
+    uuid_map,
+    world_rules::WorldRules,
+};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::storage::{ChunkRecord, ComponentRecord, EntityRecord, Storage, WorldSave};
+
+pub async fn load_world_rules(storage: &dyn Storage, world_id: &str) -> WorldRules {
+    match storage.load_world_rules(world_id).await {
+        Some(rules) => rules,
+        None => {
+            warn!(
+                "World {} has no row in worlds, using default rules",
+                world_id
+            );
+            WorldRules::new()
+        }
+    }
+}
+
+/**
+ * Loads an entity, and every item it carries since carried items have no chunk to be loaded with.
+ */
+pub async fn load_entity(
+    storage: &dyn Storage,
+    entity_id: entity_id::EntityId,
+    world: &mut GameWorld,
+    registry: &Registry,
+) {
+    let mut to_load = vec![entity_id];
+    while let Some(id) = to_load.pop() {
+        load_single_entity(storage, id, world, registry).await;
+        to_load.extend(inventory::get_carried_items(world, id));
+    }
+}
+async fn load_single_entity(
+    storage: &dyn Storage,
+    entity_id: entity_id::EntityId,
+    world: &mut GameWorld,
+    registry: &Registry,
+) {
+    let components = storage.load_components(entity_id).await;
+    let ent = &mut world.spawn();
+    let ent_id = ent.id();
+    for component in components {
+        let dat = serde_json::from_str(&component.dat).unwrap();
+        registry.add_component_to_entity(ent, component.type_name, dat);
+    }
+    world
+        .get_world_mut()
+        .get_resource_mut::<uuid_map::UuidMap>()
+        .unwrap()
+        .add(entity_id, ent_id);
+}
+pub async fn retreive_all_loaded_chunks_and_entities(
+    storage: &dyn Storage,
+    world: &mut GameWorld,
+    registry: &Registry,
+) -> Vec<(chunk::ChunkId, chunk::Chunk)> {
+    let chunk_ids = storage.loaded_chunk_ids(world.get_world_name()).await;
+    //load every chunk in using load_chunk_and_entities
+    let mut chunks = Vec::new();
+    for chunk_id in chunk_ids {
+        match load_chunk_and_entities(storage, chunk_id, world, registry).await {
+            Some(chunk) => {
+                chunks.push((chunk_id, chunk));
+            }
+            None => {
+                tracing::error!("Error loading chunk");
+            }
+        }
+    }
+    chunks
+}
+
+pub async fn load_chunk_and_entities(
+    storage: &dyn Storage,
+    chunk_id: chunk::ChunkId,
+    world: &mut game_world::GameWorld,
+    registry: &Registry,
+) -> Option<chunk::Chunk> {
+    let chunk_dat = storage.load_chunk(world.get_world_name(), chunk_id).await?;
+    match Chunk::new(&chunk_dat) {
+        Ok(chunk) => {
+            for entity_id in storage
+                .entities_in_chunk(world.get_world_name(), chunk_id)
+                .await
+            {
+                load_entity(storage, entity_id, world, registry).await;
+            }
+            Some(chunk)
+        }
+        Err(_) => None,
+    }
+}
+
+pub fn add_chunk(
+    save: &mut WorldSave,
+    chunk_id: chunk::ChunkId,
+    chunk: &chunk::Chunk,
+    loaded: bool,
+) {
+    save.chunks.push(ChunkRecord {
+        chunk_id: chunk_id,
+        chunk_dat: serde_cbor::to_vec(chunk).expect("Could not serialize chunk as cbor"),
+        loaded: loaded,
+    });
+}
+/**
+ * Adds an entity with all of its components to a save, along with every item it carries.
+ */
+pub fn add_entity(
+    save: &mut WorldSave,
+    world: &GameWorld,
+    entity_id: entity_id::EntityId,
+    registry: &Registry,
+) {
+    let mut to_save = vec![entity_id];
+    while let Some(id) = to_save.pop() {
+        save.entities
+            .extend(entity_record(world, id, registry, None));
+        to_save.extend(inventory::get_carried_items(world, id));
+    }
+}
+/**
+ * Saves an entity, and every item it carries.
+ */
+pub async fn save_entity<'a>(
+    storage: &dyn Storage,
+    entity_id: entity_id::EntityId,
+    world: &'a GameWorld,
+    registry: &'a Registry,
+) {
+    let mut save = WorldSave::default();
+    add_entity(&mut save, world, entity_id, registry);
+    storage.save_world(world.get_world_name(), &save).await;
+}
+/**
+ * Saves only the chunks and components that changed since the last save, in one transaction.
+ */
+pub async fn save_unsaved_changes(
+    storage: &dyn Storage,
+    world: &mut GameWorld,
+    registry: &Registry,
+) {
+    let changes = world.take_unsaved_changes();
+    if changes.is_empty() {
+        return;
+    }
+    let mut save = WorldSave::default();
+    for chunk_id in changes.get_chunks() {
+        //chunks that were unloaded since have already been saved
+        if let Some(chunk) = world.get_chunk_map().get(*chunk_id) {
+            add_chunk(&mut save, *chunk_id, chunk, true);
+        }
+    }
+    let nothing_changed = HashSet::new();
+    for entity_id in changes.get_entities() {
+        let changed = changes
+            .get_changed_components(entity_id)
+            .unwrap_or(&nothing_changed);
+        if let Some(mut record) = entity_record(world, entity_id, registry, Some(changed)) {
+            record.removed_components = changes
+                .get_removed_components(entity_id)
+                .map(|removed| removed.iter().cloned().collect())
+                .unwrap_or_default();
+            save.entities.push(record);
+        }
+    }
+    info!(
+        "Saving {} chunks and {} entities",
+        save.chunks.len(),
+        save.entities.len()
+    );
+    storage.save_world(world.get_world_name(), &save).await;
+}
+/**
+ * Serializes an entity's components, or only the named ones. Returns None if the entity isn't in the world anymore.
+ */
+fn entity_record(
+    world: &GameWorld,
+    entity_id: entity_id::EntityId,
+    registry: &Registry,
+    only: Option<&HashSet<String>>,
+) -> Option<EntityRecord> {
+    let ent = *world.get_uuid_map().get(entity_id)?;
+    let chunk_id = match world.get_world().get::<mmolib::position::Position>(ent) {
+        Some(pos) => {
+            if pos.load_with_chunk {
+                Some(chunk::chunk_id_from_position(pos.pos))
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+    let results = registry
+        .serialize_components(world.get_world(), ent)
+        .into_iter()
+        .filter(|(type_name, _)| only.map_or(true, |only| only.contains(type_name)))
+        .map(|(type_name, value)| ComponentRecord {
+            type_name: type_name,
+            dat: value.to_string(),
+        })
+        .collect();
+    Some(EntityRecord {
+        entity_id: entity_id,
+        chunk_id: chunk_id,
+        components: results,
+        removed_components: Vec::new(),
+    })
+}
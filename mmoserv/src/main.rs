@@ -4,15 +4,24 @@
 #![deny(warnings)]
 use clap::Parser;
 use serde_json::Value;
-use tracing::{info, subscriber};
+use tracing::{error, info, subscriber};
 mod args;
+mod commands;
 mod complex;
 mod connection;
 mod flat_world_generator;
 mod game;
+mod loaders;
+mod memory_storage;
+mod metered_storage;
+mod metrics;
+mod migrations;
+mod mysql_storage;
 mod server;
 mod server_request;
-mod sql_loaders;
+mod shutdown;
+mod sqlite_storage;
+mod storage;
 use std::time::Duration;
 #[tokio::main]
 async fn main() {
@@ -20,6 +29,16 @@ async fn main() {
         .with_max_level(tracing::Level::INFO)
         .init();
     let args = args::Args::parse();
+    if args.migrate_only {
+        storage::connect(&args).await.migrate().await;
+        info!("Database schema is up to date");
+        return;
+    }
     let mut server = server::Server::new(&args).await;
-    server.run_game().await;
+    if server.run_game().await {
+        info!("Shutdown complete");
+    } else {
+        error!("Shutdown finished with errors, some worlds may not have been saved");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,529 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use mmolib::{
+    chunk::ChunkId, entity_id::EntityId, server_response_type::ErrorCode, world_rules::WorldRules,
+};
+
+use crate::storage::{ComponentRecord, SessionRecord, Storage, UserRecord, WorldSave};
+
+struct StoredChunk {
+    chunk_dat: Vec<u8>,
+    loaded: bool,
+}
+
+struct StoredEntity {
+    world_id: String,
+    chunk_id: Option<ChunkId>,
+    components: HashMap<String, String>,
+}
+
+struct InviteCode {
+    created_by: String,
+    uses_remaining: u32,
+    expires_at: u64,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    worlds: HashMap<String, WorldRules>,
+    chunks: HashMap<(String, ChunkId), StoredChunk>,
+    entities: HashMap<EntityId, StoredEntity>,
+    players: HashMap<EntityId, String>,
+    users: HashMap<String, UserRecord>,
+    invite_codes: HashMap<String, InviteCode>,
+    sessions: HashMap<String, SessionRecord>,
+}
+
+/**
+ * Keeps everything in memory, nothing survives a restart. Useful for testing and throwaway servers.
+ */
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            data: Mutex::new(MemoryData::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self) {}
+
+    async fn create_world(&self, world_id: &str) -> bool {
+        let mut data = self.data.lock().unwrap();
+        if data.worlds.contains_key(world_id) {
+            return false;
+        }
+        data.worlds.insert(world_id.to_owned(), WorldRules::new());
+        true
+    }
+    async fn world_exists(&self, world_id: &str) -> bool {
+        self.data.lock().unwrap().worlds.contains_key(world_id)
+    }
+    async fn load_world_rules(&self, world_id: &str) -> Option<WorldRules> {
+        self.data.lock().unwrap().worlds.get(world_id).cloned()
+    }
+    async fn save_world_rules(&self, world_id: &str, rules: &WorldRules) {
+        if let Some(r) = self.data.lock().unwrap().worlds.get_mut(world_id) {
+            *r = rules.clone();
+        }
+    }
+
+    async fn chunk_exists(&self, world_id: &str, chunk_id: ChunkId) -> bool {
+        self.data
+            .lock()
+            .unwrap()
+            .chunks
+            .contains_key(&(world_id.to_owned(), chunk_id))
+    }
+    async fn load_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Option<Vec<u8>> {
+        let mut data = self.data.lock().unwrap();
+        let chunk = data.chunks.get_mut(&(world_id.to_owned(), chunk_id))?;
+        chunk.loaded = true;
+        Some(chunk.chunk_dat.clone())
+    }
+    async fn loaded_chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        self.data
+            .lock()
+            .unwrap()
+            .chunks
+            .iter()
+            .filter(|((world, _), chunk)| world == world_id && chunk.loaded)
+            .map(|((_, chunk_id), _)| *chunk_id)
+            .collect()
+    }
+    async fn chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        self.data
+            .lock()
+            .unwrap()
+            .chunks
+            .keys()
+            .filter(|(world, _)| world == world_id)
+            .map(|(_, chunk_id)| *chunk_id)
+            .collect()
+    }
+
+    async fn entities_in_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Vec<EntityId> {
+        self.data
+            .lock()
+            .unwrap()
+            .entities
+            .iter()
+            .filter(|(_, e)| e.world_id == world_id && e.chunk_id == Some(chunk_id))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+    async fn load_components(&self, entity_id: EntityId) -> Vec<ComponentRecord> {
+        match self.data.lock().unwrap().entities.get(&entity_id) {
+            Some(e) => e
+                .components
+                .iter()
+                .map(|(type_name, dat)| ComponentRecord {
+                    type_name: type_name.clone(),
+                    dat: dat.clone(),
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    async fn save_world(&self, world_id: &str, save: &WorldSave) {
+        let mut data = self.data.lock().unwrap();
+        for chunk in &save.chunks {
+            data.chunks.insert(
+                (world_id.to_owned(), chunk.chunk_id),
+                StoredChunk {
+                    chunk_dat: chunk.chunk_dat.clone(),
+                    loaded: chunk.loaded,
+                },
+            );
+        }
+        for entity in &save.entities {
+            let stored = data
+                .entities
+                .entry(entity.entity_id)
+                .or_insert_with(|| StoredEntity {
+                    world_id: world_id.to_owned(),
+                    chunk_id: None,
+                    components: HashMap::new(),
+                });
+            stored.chunk_id = entity.chunk_id;
+            for component in &entity.components {
+                stored
+                    .components
+                    .insert(component.type_name.clone(), component.dat.clone());
+            }
+            for type_name in &entity.removed_components {
+                stored.components.remove(type_name);
+            }
+        }
+    }
+    async fn delete_entity(&self, entity_id: EntityId) {
+        let mut data = self.data.lock().unwrap();
+        data.entities.remove(&entity_id);
+        data.players.remove(&entity_id);
+    }
+
+    async fn find_player(&self, world_id: &str, username: &str) -> Option<EntityId> {
+        let data = self.data.lock().unwrap();
+        data.players
+            .iter()
+            .find(|(id, user)| {
+                *user == username
+                    && data
+                        .entities
+                        .get(id)
+                        .map_or(false, |e| e.world_id == world_id)
+            })
+            .map(|(id, _)| *id)
+    }
+    async fn add_player_to_user(&self, username: &str, entity_id: EntityId) {
+        self.data
+            .lock()
+            .unwrap()
+            .players
+            .insert(entity_id, username.to_owned());
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str, is_admin: bool) -> bool {
+        let mut data = self.data.lock().unwrap();
+        if data.users.contains_key(username) {
+            return false;
+        }
+        data.users.insert(
+            username.to_owned(),
+            UserRecord {
+                user_name: username.to_owned(),
+                password_hash: password_hash.to_owned(),
+                is_admin: is_admin,
+                invite_quota: None,
+                banned: false,
+            },
+        );
+        true
+    }
+    async fn get_user(&self, username: &str) -> Option<UserRecord> {
+        self.data.lock().unwrap().users.get(username).cloned()
+    }
+    async fn set_invite_quota(&self, username: &str, quota: u32) -> bool {
+        match self.data.lock().unwrap().users.get_mut(username) {
+            Some(u) => {
+                u.invite_quota = Some(quota);
+                true
+            }
+            None => false,
+        }
+    }
+    async fn set_banned(&self, username: &str, banned: bool) -> bool {
+        match self.data.lock().unwrap().users.get_mut(username) {
+            Some(u) => {
+                u.banned = banned;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn create_invite_code(
+        &self,
+        username: &str,
+        code: &str,
+        uses: u32,
+        expires_at: u64,
+        default_quota: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        let mut data = self.data.lock().unwrap();
+        let quota = match data.users.get(username) {
+            Some(u) => u.invite_quota,
+            None => return Err(ErrorCode::UserNotFound),
+        };
+        if let Some(default_quota) = default_quota {
+            let issued = data
+                .invite_codes
+                .values()
+                .filter(|c| c.created_by == username)
+                .count();
+            if issued >= quota.unwrap_or(default_quota) as usize {
+                return Err(ErrorCode::InviteQuotaReached);
+            }
+        }
+        data.invite_codes.insert(
+            code.to_owned(),
+            InviteCode {
+                created_by: username.to_owned(),
+                uses_remaining: uses,
+                expires_at: expires_at,
+            },
+        );
+        Ok(())
+    }
+    async fn register_user_with_invite_code(
+        &self,
+        username: &str,
+        password_hash: &str,
+        invite_code: &str,
+        now: u64,
+    ) -> Result<(), ErrorCode> {
+        let mut data = self.data.lock().unwrap();
+        match data.invite_codes.get(invite_code) {
+            Some(c) => {
+                if c.uses_remaining == 0 || c.expires_at <= now {
+                    return Err(ErrorCode::InvalidInviteCode);
+                }
+            }
+            None => return Err(ErrorCode::InvalidInviteCode),
+        }
+        if data.users.contains_key(username) {
+            return Err(ErrorCode::AlreadyExists);
+        }
+        data.users.insert(
+            username.to_owned(),
+            UserRecord {
+                user_name: username.to_owned(),
+                password_hash: password_hash.to_owned(),
+                is_admin: false,
+                invite_quota: None,
+                banned: false,
+            },
+        );
+        data.invite_codes
+            .get_mut(invite_code)
+            .unwrap()
+            .uses_remaining -= 1;
+        Ok(())
+    }
+
+    async fn create_session(&self, session: &SessionRecord) {
+        self.data
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(session.session_id.clone(), session.clone());
+    }
+    async fn get_session(&self, session_id: &str) -> Option<SessionRecord> {
+        self.data.lock().unwrap().sessions.get(session_id).cloned()
+    }
+    async fn rotate_session_refresh_hash(
+        &self,
+        session_id: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> bool {
+        match self.data.lock().unwrap().sessions.get_mut(session_id) {
+            Some(s) if !s.revoked && s.refresh_hash == old_hash => {
+                s.refresh_hash = new_hash.to_owned();
+                true
+            }
+            _ => false,
+        }
+    }
+    async fn revoke_session(&self, session_id: &str) {
+        if let Some(s) = self.data.lock().unwrap().sessions.get_mut(session_id) {
+            s.revoked = true;
+        }
+    }
+    async fn active_session_ids(&self, username: &str) -> Vec<String> {
+        self.data
+            .lock()
+            .unwrap()
+            .sessions
+            .values()
+            .filter(|s| s.user_name == username && !s.revoked)
+            .map(|s| s.session_id.clone())
+            .collect()
+    }
+    async fn revoked_session_ids(&self, now: u64) -> Vec<String> {
+        self.data
+            .lock()
+            .unwrap()
+            .sessions
+            .values()
+            .filter(|s| s.revoked && s.expires_at > now)
+            .map(|s| s.session_id.clone())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn test_save_world_round_trip() {
+    use crate::storage::{ChunkRecord, EntityRecord};
+    let storage = MemoryStorage::new();
+    assert!(storage.create_world("world").await);
+    assert!(!storage.create_world("world").await);
+    let chunk_id = ChunkId::new_raw(3);
+    let entity_id = EntityId::new_with_number(7);
+    let component = |type_name: &str, dat: &str| ComponentRecord {
+        type_name: type_name.to_owned(),
+        dat: dat.to_owned(),
+    };
+    storage
+        .save_world(
+            "world",
+            &WorldSave {
+                chunks: vec![ChunkRecord {
+                    chunk_id: chunk_id,
+                    chunk_dat: vec![1, 2, 3],
+                    loaded: true,
+                }],
+                entities: vec![EntityRecord {
+                    entity_id: entity_id,
+                    chunk_id: Some(chunk_id),
+                    components: vec![component("health", "10"), component("position", "[1,2]")],
+                    removed_components: Vec::new(),
+                }],
+            },
+        )
+        .await;
+    assert_eq!(storage.loaded_chunk_ids("world").await, vec![chunk_id]);
+    assert_eq!(
+        storage.load_chunk("world", chunk_id).await,
+        Some(vec![1, 2, 3])
+    );
+    assert_eq!(
+        storage.entities_in_chunk("world", chunk_id).await,
+        vec![entity_id]
+    );
+    assert_eq!(storage.load_components(entity_id).await.len(), 2);
+    //a later save only carries what changed
+    storage
+        .save_world(
+            "world",
+            &WorldSave {
+                chunks: Vec::new(),
+                entities: vec![EntityRecord {
+                    entity_id: entity_id,
+                    chunk_id: Some(chunk_id),
+                    components: vec![component("health", "4")],
+                    removed_components: vec![String::from("position")],
+                }],
+            },
+        )
+        .await;
+    let components = storage.load_components(entity_id).await;
+    assert_eq!(components.len(), 1);
+    assert_eq!(components[0].dat, "4");
+    storage.add_player_to_user("alice", entity_id).await;
+    assert_eq!(storage.find_player("world", "alice").await, Some(entity_id));
+    assert_eq!(storage.find_player("other", "alice").await, None);
+    storage.delete_entity(entity_id).await;
+    assert!(storage
+        .entities_in_chunk("world", chunk_id)
+        .await
+        .is_empty());
+    assert_eq!(storage.find_player("world", "alice").await, None);
+}
+
+#[tokio::test]
+async fn test_sessions() {
+    let storage = MemoryStorage::new();
+    storage.create_user("alice", "hash", false).await;
+    storage
+        .create_session(&SessionRecord {
+            session_id: String::from("s1"),
+            user_name: String::from("alice"),
+            refresh_hash: String::from("first"),
+            expires_at: 100,
+            revoked: false,
+        })
+        .await;
+    assert!(
+        !storage
+            .rotate_session_refresh_hash("s1", "wrong", "second")
+            .await
+    );
+    assert!(
+        storage
+            .rotate_session_refresh_hash("s1", "first", "second")
+            .await
+    );
+    //the old token lost the race as soon as it was rotated
+    assert!(
+        !storage
+            .rotate_session_refresh_hash("s1", "first", "third")
+            .await
+    );
+    assert_eq!(
+        storage.get_session("s1").await.unwrap().refresh_hash,
+        "second"
+    );
+    assert_eq!(storage.active_session_ids("alice").await, vec!["s1"]);
+    storage.revoke_session("s1").await;
+    assert!(
+        !storage
+            .rotate_session_refresh_hash("s1", "second", "third")
+            .await
+    );
+    assert!(storage.active_session_ids("alice").await.is_empty());
+    assert_eq!(storage.revoked_session_ids(50).await, vec!["s1"]);
+    assert!(storage.revoked_session_ids(100).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_invite_codes() {
+    let storage = MemoryStorage::new();
+    storage.create_user("alice", "hash", false).await;
+    assert_eq!(
+        storage
+            .create_invite_code("nobody", "code0", 1, 100, Some(1))
+            .await,
+        Err(ErrorCode::UserNotFound)
+    );
+    assert_eq!(
+        storage
+            .create_invite_code("alice", "code1", 1, 100, Some(1))
+            .await,
+        Ok(())
+    );
+    assert_eq!(
+        storage
+            .create_invite_code("alice", "code2", 1, 100, Some(1))
+            .await,
+        Err(ErrorCode::InviteQuotaReached)
+    );
+    //an explicit quota overrides the default
+    storage.set_invite_quota("alice", 2).await;
+    assert_eq!(
+        storage
+            .create_invite_code("alice", "code2", 1, 10, Some(1))
+            .await,
+        Ok(())
+    );
+    assert_eq!(
+        storage
+            .register_user_with_invite_code("alice", "hash", "code1", 50)
+            .await,
+        Err(ErrorCode::AlreadyExists)
+    );
+    assert_eq!(
+        storage
+            .register_user_with_invite_code("bob", "hash", "code1", 50)
+            .await,
+        Ok(())
+    );
+    assert!(storage.get_user("bob").await.is_some());
+    //used up
+    assert_eq!(
+        storage
+            .register_user_with_invite_code("carol", "hash", "code1", 50)
+            .await,
+        Err(ErrorCode::InvalidInviteCode)
+    );
+    //expired
+    assert_eq!(
+        storage
+            .register_user_with_invite_code("carol", "hash", "code2", 50)
+            .await,
+        Err(ErrorCode::InvalidInviteCode)
+    );
+    assert_eq!(
+        storage
+            .register_user_with_invite_code("carol", "hash", "missing", 50)
+            .await,
+        Err(ErrorCode::InvalidInviteCode)
+    );
+    assert!(storage.get_user("carol").await.is_none());
+}
@@ -0,0 +1,199 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use mmolib::{
+    chunk::ChunkId, entity_id::EntityId, server_response_type::ErrorCode, world_rules::WorldRules,
+};
+
+use crate::metrics::Metrics;
+use crate::storage::{ComponentRecord, SessionRecord, Storage, UserRecord, WorldSave};
+
+/**
+ * Wraps another storage and records how long each query takes.
+ */
+pub struct MeteredStorage {
+    inner: Arc<dyn Storage>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: Arc<dyn Storage>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            inner: inner,
+            metrics: metrics,
+        }
+    }
+    async fn time<T>(&self, query: &'static str, future: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let result = future.await;
+        self.metrics.record_query(query, started.elapsed());
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn migrate(&self) {
+        self.time("migrate", self.inner.migrate()).await
+    }
+
+    async fn create_world(&self, world_id: &str) -> bool {
+        self.time("create_world", self.inner.create_world(world_id))
+            .await
+    }
+    async fn world_exists(&self, world_id: &str) -> bool {
+        self.time("world_exists", self.inner.world_exists(world_id))
+            .await
+    }
+    async fn load_world_rules(&self, world_id: &str) -> Option<WorldRules> {
+        self.time("load_world_rules", self.inner.load_world_rules(world_id))
+            .await
+    }
+    async fn save_world_rules(&self, world_id: &str, rules: &WorldRules) {
+        self.time(
+            "save_world_rules",
+            self.inner.save_world_rules(world_id, rules),
+        )
+        .await
+    }
+
+    async fn chunk_exists(&self, world_id: &str, chunk_id: ChunkId) -> bool {
+        self.time("chunk_exists", self.inner.chunk_exists(world_id, chunk_id))
+            .await
+    }
+    async fn load_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Option<Vec<u8>> {
+        self.time("load_chunk", self.inner.load_chunk(world_id, chunk_id))
+            .await
+    }
+    async fn loaded_chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        self.time("loaded_chunk_ids", self.inner.loaded_chunk_ids(world_id))
+            .await
+    }
+    async fn chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        self.time("chunk_ids", self.inner.chunk_ids(world_id)).await
+    }
+
+    async fn entities_in_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Vec<EntityId> {
+        self.time(
+            "entities_in_chunk",
+            self.inner.entities_in_chunk(world_id, chunk_id),
+        )
+        .await
+    }
+    async fn load_components(&self, entity_id: EntityId) -> Vec<ComponentRecord> {
+        self.time("load_components", self.inner.load_components(entity_id))
+            .await
+    }
+    async fn save_world(&self, world_id: &str, save: &WorldSave) {
+        self.time("save_world", self.inner.save_world(world_id, save))
+            .await
+    }
+    async fn delete_entity(&self, entity_id: EntityId) {
+        self.time("delete_entity", self.inner.delete_entity(entity_id))
+            .await
+    }
+
+    async fn find_player(&self, world_id: &str, username: &str) -> Option<EntityId> {
+        self.time("find_player", self.inner.find_player(world_id, username))
+            .await
+    }
+    async fn add_player_to_user(&self, username: &str, entity_id: EntityId) {
+        self.time(
+            "add_player_to_user",
+            self.inner.add_player_to_user(username, entity_id),
+        )
+        .await
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str, is_admin: bool) -> bool {
+        self.time(
+            "create_user",
+            self.inner.create_user(username, password_hash, is_admin),
+        )
+        .await
+    }
+    async fn get_user(&self, username: &str) -> Option<UserRecord> {
+        self.time("get_user", self.inner.get_user(username)).await
+    }
+    async fn set_invite_quota(&self, username: &str, quota: u32) -> bool {
+        self.time(
+            "set_invite_quota",
+            self.inner.set_invite_quota(username, quota),
+        )
+        .await
+    }
+
+    async fn set_banned(&self, username: &str, banned: bool) -> bool {
+        self.time("set_banned", self.inner.set_banned(username, banned))
+            .await
+    }
+
+    async fn create_invite_code(
+        &self,
+        username: &str,
+        code: &str,
+        uses: u32,
+        expires_at: u64,
+        default_quota: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        self.time(
+            "create_invite_code",
+            self.inner
+                .create_invite_code(username, code, uses, expires_at, default_quota),
+        )
+        .await
+    }
+    async fn register_user_with_invite_code(
+        &self,
+        username: &str,
+        password_hash: &str,
+        invite_code: &str,
+        now: u64,
+    ) -> Result<(), ErrorCode> {
+        self.time(
+            "register_user_with_invite_code",
+            self.inner
+                .register_user_with_invite_code(username, password_hash, invite_code, now),
+        )
+        .await
+    }
+
+    async fn create_session(&self, session: &SessionRecord) {
+        self.time("create_session", self.inner.create_session(session))
+            .await
+    }
+    async fn get_session(&self, session_id: &str) -> Option<SessionRecord> {
+        self.time("get_session", self.inner.get_session(session_id))
+            .await
+    }
+    async fn rotate_session_refresh_hash(
+        &self,
+        session_id: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> bool {
+        self.time(
+            "rotate_session_refresh_hash",
+            self.inner
+                .rotate_session_refresh_hash(session_id, old_hash, new_hash),
+        )
+        .await
+    }
+    async fn revoke_session(&self, session_id: &str) {
+        self.time("revoke_session", self.inner.revoke_session(session_id))
+            .await
+    }
+    async fn active_session_ids(&self, username: &str) -> Vec<String> {
+        self.time(
+            "active_session_ids",
+            self.inner.active_session_ids(username),
+        )
+        .await
+    }
+    async fn revoked_session_ids(&self, now: u64) -> Vec<String> {
+        self.time("revoked_session_ids", self.inner.revoked_session_ids(now))
+            .await
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task;
+use tracing::{info, warn};
+
+/**
+ * Times consecutive phases of a tick, each mark records the time since the previous one.
+ */
+pub struct PhaseTimer {
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl PhaseTimer {
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+    pub fn mark(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.last));
+        self.last = now;
+    }
+    pub fn into_phases(self) -> Vec<(&'static str, Duration)> {
+        self.phases
+    }
+}
+
+/**
+ * What one tick of a world measured.
+ */
+pub struct TickReport {
+    pub tick: u64,
+    pub duration: Duration,
+    pub phases: Vec<(&'static str, Duration)>,
+    pub entities: usize,
+    pub chunks: usize,
+    pub players: usize,
+    pub bytes_sent: u64,
+    pub overruns: u64,
+    pub skipped_ticks: u64,
+}
+
+#[derive(Default)]
+struct PhaseStats {
+    last: Duration,
+    total: Duration,
+}
+
+#[derive(Default)]
+struct WorldMetrics {
+    tick: u64,
+    ticks: u64,
+    tick_duration: Duration,
+    phases: BTreeMap<&'static str, PhaseStats>,
+    entities: usize,
+    chunks: usize,
+    players: usize,
+    bytes_sent: u64,
+    bytes_sent_total: u64,
+    overruns: u64,
+    skipped_ticks: u64,
+}
+
+#[derive(Default)]
+struct QueryStats {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+/**
+ * Numbers collected from every world and the storage backend, rendered in the prometheus text format.
+ */
+#[derive(Default)]
+pub struct Metrics {
+    worlds: Mutex<BTreeMap<String, WorldMetrics>>,
+    queries: Mutex<BTreeMap<&'static str, QueryStats>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn record_tick(&self, world_name: &str, report: TickReport) {
+        let mut worlds = self.worlds.lock().unwrap();
+        let world = worlds.entry(world_name.to_owned()).or_default();
+        world.tick = report.tick;
+        world.ticks += 1;
+        world.tick_duration = report.duration;
+        //phases that didn't run this tick, like saving, keep their last duration
+        for (phase, duration) in report.phases {
+            let stats = world.phases.entry(phase).or_default();
+            stats.last = duration;
+            stats.total += duration;
+        }
+        world.entities = report.entities;
+        world.chunks = report.chunks;
+        world.players = report.players;
+        world.bytes_sent = report.bytes_sent;
+        world.bytes_sent_total += report.bytes_sent;
+        world.overruns = report.overruns;
+        world.skipped_ticks = report.skipped_ticks;
+    }
+    pub fn record_query(&self, query: &'static str, duration: Duration) {
+        let mut queries = self.queries.lock().unwrap();
+        let stats = queries.entry(query).or_default();
+        stats.count += 1;
+        stats.total += duration;
+        stats.max = stats.max.max(duration);
+    }
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let worlds = self.worlds.lock().unwrap();
+        let world_gauges: [(&str, &str, &str, fn(&WorldMetrics) -> f64); 10] = [
+            ("mmo_tick", "gauge", "Number of the last tick run", |w| {
+                w.tick as f64
+            }),
+            ("mmo_ticks_total", "counter", "Ticks run since start", |w| {
+                w.ticks as f64
+            }),
+            (
+                "mmo_tick_duration_seconds",
+                "gauge",
+                "Time the last tick took",
+                |w| w.tick_duration.as_secs_f64(),
+            ),
+            (
+                "mmo_tick_overruns_total",
+                "counter",
+                "Ticks that took longer than the tick step",
+                |w| w.overruns as f64,
+            ),
+            (
+                "mmo_tick_skipped_total",
+                "counter",
+                "Ticks dropped because the world fell too far behind",
+                |w| w.skipped_ticks as f64,
+            ),
+            ("mmo_entities", "gauge", "Entities in the world", |w| {
+                w.entities as f64
+            }),
+            (
+                "mmo_loaded_chunks",
+                "gauge",
+                "Chunks loaded in the world",
+                |w| w.chunks as f64,
+            ),
+            (
+                "mmo_players",
+                "gauge",
+                "Players connected to the world",
+                |w| w.players as f64,
+            ),
+            (
+                "mmo_tick_bytes_sent",
+                "gauge",
+                "Bytes of ticked messages sent in the last tick",
+                |w| w.bytes_sent as f64,
+            ),
+            (
+                "mmo_bytes_sent_total",
+                "counter",
+                "Bytes of ticked messages sent since start",
+                |w| w.bytes_sent_total as f64,
+            ),
+        ];
+        for (name, kind, help, value) in world_gauges {
+            write_header(&mut out, name, kind, help);
+            for (world_name, world) in worlds.iter() {
+                writeln!(
+                    out,
+                    "{}{{world=\"{}\"}} {}",
+                    name,
+                    escape_label(world_name),
+                    value(world)
+                );
+            }
+        }
+        write_header(
+            &mut out,
+            "mmo_tick_phase_seconds",
+            "gauge",
+            "Time spent in each phase the last time it ran",
+        );
+        for (world_name, world) in worlds.iter() {
+            for (phase, stats) in &world.phases {
+                writeln!(
+                    out,
+                    "mmo_tick_phase_seconds{{world=\"{}\",phase=\"{}\"}} {}",
+                    escape_label(world_name),
+                    phase,
+                    stats.last.as_secs_f64()
+                );
+            }
+        }
+        write_header(
+            &mut out,
+            "mmo_tick_phase_seconds_total",
+            "counter",
+            "Time spent in each phase since start",
+        );
+        for (world_name, world) in worlds.iter() {
+            for (phase, stats) in &world.phases {
+                writeln!(
+                    out,
+                    "mmo_tick_phase_seconds_total{{world=\"{}\",phase=\"{}\"}} {}",
+                    escape_label(world_name),
+                    phase,
+                    stats.total.as_secs_f64()
+                );
+            }
+        }
+        drop(worlds);
+        let queries = self.queries.lock().unwrap();
+        write_header(
+            &mut out,
+            "mmo_db_query_seconds",
+            "summary",
+            "Time spent in storage queries",
+        );
+        for (query, stats) in queries.iter() {
+            writeln!(
+                out,
+                "mmo_db_query_seconds_sum{{query=\"{}\"}} {}",
+                query,
+                stats.total.as_secs_f64()
+            );
+            writeln!(
+                out,
+                "mmo_db_query_seconds_count{{query=\"{}\"}} {}",
+                query, stats.count
+            );
+        }
+        write_header(
+            &mut out,
+            "mmo_db_query_max_seconds",
+            "gauge",
+            "Slowest run of each storage query",
+        );
+        for (query, stats) in queries.iter() {
+            writeln!(
+                out,
+                "mmo_db_query_max_seconds{{query=\"{}\"}} {}",
+                query,
+                stats.max.as_secs_f64()
+            );
+        }
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help);
+    writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/**
+ * Answers every http request on the listener with the current metrics. Scrapers only ever GET one path, so the request isn't parsed.
+ */
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    info!(
+        "Serving metrics on {}",
+        listener
+            .local_addr()
+            .map(|a| a.to_string())
+            .unwrap_or_default()
+    );
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Could not accept metrics connection: {}", e);
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        task::spawn(async move {
+            let mut buf = [0u8; 1024];
+            if stream.read(&mut buf).await.is_err() {
+                return;
+            }
+            let body = metrics.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).await;
+            stream.shutdown().await;
+        });
+    }
+}
+
+#[test]
+fn test_render() {
+    let metrics = Metrics::new();
+    let mut timer = PhaseTimer::new();
+    timer.mark("load_and_unload_chunks");
+    timer.mark("clear_trackers");
+    let phases = timer.into_phases();
+    assert_eq!(
+        phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>(),
+        vec!["load_and_unload_chunks", "clear_trackers"]
+    );
+    metrics.record_tick(
+        "w\"1",
+        TickReport {
+            tick: 7,
+            duration: Duration::from_millis(20),
+            phases: phases,
+            entities: 3,
+            chunks: 2,
+            players: 1,
+            bytes_sent: 100,
+            overruns: 0,
+            skipped_ticks: 0,
+        },
+    );
+    metrics.record_query("load_chunk", Duration::from_millis(5));
+    metrics.record_query("load_chunk", Duration::from_millis(15));
+    let out = metrics.render();
+    assert!(out.contains("# TYPE mmo_tick gauge\n"));
+    //label values are escaped
+    assert!(out.contains("mmo_tick{world=\"w\\\"1\"} 7\n"));
+    assert!(out.contains("mmo_tick_duration_seconds{world=\"w\\\"1\"} 0.02\n"));
+    assert!(out.contains("mmo_bytes_sent_total{world=\"w\\\"1\"} 100\n"));
+    assert!(out.contains("mmo_tick_phase_seconds{world=\"w\\\"1\",phase=\"clear_trackers\"} "));
+    assert!(out.contains("mmo_db_query_seconds_count{query=\"load_chunk\"} 2\n"));
+    assert!(out.contains("mmo_db_query_seconds_sum{query=\"load_chunk\"} 0.02\n"));
+    assert!(out.contains("mmo_db_query_max_seconds{query=\"load_chunk\"} 0.015\n"));
+}
@@ -0,0 +1,98 @@
+/**
+ * A schema change. Migrations are applied in order of version and each version is recorded in the schema_version table, so a migration only ever runs once per database.
+ * Every step is safe to run again, so a migration that failed halfway, or a schema created before migrations existed, is brought up to date by running it again.
+ */
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub steps: &'static [Step],
+}
+
+pub enum Step {
+    /**
+     * Sql that is already safe to run twice, like CREATE TABLE IF NOT EXISTS.
+     */
+    Sql(&'static str),
+    /**
+     * Skipped if the table already has the column.
+     */
+    AddColumn {
+        table: &'static str,
+        column: &'static str,
+        definition: &'static str,
+    },
+    /**
+     * Skipped if an index with the name exists. A unique index is refused while the table holds duplicate values.
+     */
+    CreateIndex {
+        table: &'static str,
+        name: &'static str,
+        columns: &'static [&'static str],
+        unique: bool,
+    },
+    /**
+     * Drops every foreign key from table.column to the referenced table, found by column because the database named it.
+     */
+    DropForeignKey {
+        table: &'static str,
+        column: &'static str,
+        referenced_table: &'static str,
+    },
+}
+
+pub fn add_column_sql(table: &str, column: &str, definition: &str) -> String {
+    format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition)
+}
+
+pub fn create_index_sql(table: &str, name: &str, columns: &[&str], unique: bool) -> String {
+    format!(
+        "CREATE {}INDEX {} ON {} ({})",
+        if unique { "UNIQUE " } else { "" },
+        name,
+        table,
+        columns.join(", ")
+    )
+}
+
+/**
+ * Counts the groups of rows that share the same values in the columns.
+ */
+pub fn count_duplicates_sql(table: &str, columns: &[&str]) -> String {
+    format!(
+        "SELECT COUNT(*) AS duplicates FROM (SELECT 1 FROM {} GROUP BY {} HAVING COUNT(*) > 1) AS d",
+        table,
+        columns.join(", ")
+    )
+}
+
+/**
+ * Stops the migration when a unique index can't be created, rows are never deleted to make it fit.
+ */
+pub fn refuse_duplicates(table: &str, name: &str, columns: &[&str], duplicates: i64) {
+    if duplicates > 0 {
+        panic!(
+            "Can't create unique index {}: {} values of ({}) in {} are used more than once. Resolve them and migrate again",
+            name,
+            duplicates,
+            columns.join(", "),
+            table
+        );
+    }
+}
+
+/**
+ * Returns the migrations that still have to be applied to a database at the given version.
+ */
+pub fn pending(migrations: &'static [Migration], current_version: u32) -> Vec<&'static Migration> {
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if current_version > latest {
+        panic!(
+            "Database schema version {} is newer than the latest version {} known to this server",
+            current_version, latest
+        );
+    }
+    migrations
+        .iter()
+        .filter(|m| m.version > current_version)
+        .collect()
+}
@@ -0,0 +1,777 @@
+use async_trait::async_trait;
+use mmolib::{
+    chunk::ChunkId, entity_id::EntityId, server_response_type::ErrorCode, world_rules::WorldRules,
+};
+use sqlx::{
+    mysql::{MySqlConnectOptions, MySqlPoolOptions},
+    ConnectOptions, MySql, Pool, QueryBuilder, Row,
+};
+use tracing::info;
+
+use crate::{
+    args,
+    migrations::{self, Migration, Step},
+    storage::{ComponentRecord, SessionRecord, Storage, UserRecord, WorldSave},
+};
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        steps: &[
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS worlds (
+            world_id VARCHAR(50) PRIMARY KEY NOT NULL)",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS users (
+            user_id INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+            user_name TEXT,
+            password_hash TEXT,
+            admin BOOLEAN)",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS chunks (
+            chunk_id BIGINT UNSIGNED,
+            world_id VARCHAR(50)  NOT NULL,
+            chunk_dat BLOB,
+            loaded BOOLEAN,
+            FOREIGN KEY (world_id)
+                REFERENCES worlds(world_id)
+                ON DELETE CASCADE,
+            PRIMARY KEY (chunk_id,world_id))",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS entities (
+            entity_id BIGINT UNSIGNED PRIMARY KEY,
+            chunk_id BIGINT UNSIGNED,
+            world_id VARCHAR(50) NOT NULL,
+            FOREIGN KEY(chunk_id) 
+                REFERENCES chunks(chunk_id),
+            FOREIGN KEY(world_id)
+                REFERENCES worlds(world_id)
+                ON DELETE CASCADE
+            )",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS components (
+            type_id VARCHAR(50),
+            entity_id BIGINT UNSIGNED, 
+            dat TEXT,
+            FOREIGN KEY(entity_id) 
+                REFERENCES entities(entity_id)
+                ON DELETE CASCADE,
+            PRIMARY KEY (entity_id,type_id))",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS players (
+            player_id BIGINT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+            user_id INT,
+            entity_id BIGINT UNSIGNED,
+            FOREIGN KEY(user_id) 
+                REFERENCES users(user_id)
+                ON DELETE CASCADE,
+            FOREIGN KEY(entity_id) 
+                REFERENCES entities(entity_id)
+                ON DELETE CASCADE)",
+            ),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "world rules",
+        steps: &[
+            Step::AddColumn {
+                table: "worlds",
+                column: "pvp",
+                definition: "BOOLEAN NOT NULL DEFAULT TRUE",
+            },
+            Step::AddColumn {
+                table: "worlds",
+                column: "keep_inventory",
+                definition: "BOOLEAN NOT NULL DEFAULT FALSE",
+            },
+            Step::AddColumn {
+                table: "worlds",
+                column: "spawn_x",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 128",
+            },
+            Step::AddColumn {
+                table: "worlds",
+                column: "spawn_y",
+                definition: "INT UNSIGNED NOT NULL DEFAULT 128",
+            },
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "invite codes",
+        steps: &[
+            Step::AddColumn {
+                table: "users",
+                column: "invite_quota",
+                definition: "INT UNSIGNED",
+            },
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS invite_codes (
+            code VARCHAR(32) PRIMARY KEY NOT NULL,
+            created_by INT,
+            uses_remaining INT UNSIGNED NOT NULL,
+            expires_at BIGINT UNSIGNED NOT NULL,
+            FOREIGN KEY(created_by)
+                REFERENCES users(user_id)
+                ON DELETE CASCADE)",
+            ),
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "sessions",
+        steps: &[Step::Sql(
+            r"CREATE TABLE IF NOT EXISTS sessions (
+            session_id VARCHAR(32) PRIMARY KEY NOT NULL,
+            user_id INT NOT NULL,
+            refresh_hash TEXT NOT NULL,
+            expires_at BIGINT UNSIGNED NOT NULL,
+            revoked BOOLEAN NOT NULL DEFAULT FALSE,
+            FOREIGN KEY(user_id)
+                REFERENCES users(user_id)
+                ON DELETE CASCADE)",
+        )],
+    },
+    Migration {
+        version: 5,
+        description: "unique user names",
+        steps: &[
+            Step::Sql("ALTER TABLE users MODIFY user_name VARCHAR(50) NOT NULL"),
+            Step::CreateIndex {
+                table: "users",
+                name: "users_user_name",
+                columns: &["user_name"],
+                unique: true,
+            },
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "drop foreign key from entities to the non unique chunks.chunk_id",
+        steps: &[
+            //entities are saved before the chunk they are in, so a (chunk_id, world_id) key would not hold either
+            Step::DropForeignKey {
+                table: "entities",
+                column: "chunk_id",
+                referenced_table: "chunks",
+            },
+            Step::CreateIndex {
+                table: "entities",
+                name: "entities_world_chunk",
+                columns: &["world_id", "chunk_id"],
+                unique: false,
+            },
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "banned users",
+        steps: &[Step::AddColumn {
+            table: "users",
+            column: "banned",
+            definition: "BOOLEAN NOT NULL DEFAULT FALSE",
+        }],
+    },
+];
+
+/**
+ * Rows per multi-row statement when saving a world.
+ */
+const BATCH_SIZE: usize = 500;
+
+pub struct MySqlStorage {
+    pool: Pool<MySql>,
+}
+
+impl MySqlStorage {
+    pub async fn connect(args: &args::Args) -> Self {
+        info!(
+            "Connecting to database at mysql://{}@{}/{}",
+            args.database_user, args.database_host, args.database_name
+        );
+        let mut opts = MySqlConnectOptions::new()
+            .host(&args.database_host)
+            .username(&args.database_user)
+            .database(&args.database_name)
+            .password(&args.database_pass);
+        opts.disable_statement_logging();
+
+        let pool = MySqlPoolOptions::new()
+            .max_connections(5)
+            .connect_with(opts)
+            .await
+            .expect("Could not get db conn");
+        info!("Database connection established");
+        Self { pool: pool }
+    }
+    async fn count(&self, sql: &str, binds: &[&str]) -> i64 {
+        let mut query = sqlx::query(sql);
+        for bind in binds {
+            query = query.bind(*bind);
+        }
+        query
+            .fetch_one(&self.pool)
+            .await
+            .expect("Could not inspect schema")
+            .get(0)
+    }
+    async fn execute(&self, sql: &str) {
+        sqlx::query(sql)
+            .execute(&self.pool)
+            .await
+            .expect("Could not apply migration");
+    }
+    async fn apply_step(&self, step: &Step) {
+        match step {
+            Step::Sql(sql) => self.execute(sql).await,
+            Step::AddColumn {
+                table,
+                column,
+                definition,
+            } => {
+                let exists = self
+                    .count(
+                        "SELECT COUNT(*) FROM information_schema.COLUMNS WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?",
+                        &[table, column],
+                    )
+                    .await;
+                if exists == 0 {
+                    self.execute(&migrations::add_column_sql(table, column, definition))
+                        .await;
+                }
+            }
+            Step::CreateIndex {
+                table,
+                name,
+                columns,
+                unique,
+            } => {
+                let exists = self
+                    .count(
+                        "SELECT COUNT(*) FROM information_schema.STATISTICS WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND INDEX_NAME = ?",
+                        &[table, name],
+                    )
+                    .await;
+                if exists > 0 {
+                    return;
+                }
+                if *unique {
+                    let duplicates = self
+                        .count(&migrations::count_duplicates_sql(table, columns), &[])
+                        .await;
+                    migrations::refuse_duplicates(table, name, columns, duplicates);
+                }
+                self.execute(&migrations::create_index_sql(table, name, columns, *unique))
+                    .await;
+            }
+            Step::DropForeignKey {
+                table,
+                column,
+                referenced_table,
+            } => {
+                let names: Vec<String> = sqlx::query(
+                    "SELECT CONSTRAINT_NAME FROM information_schema.KEY_COLUMN_USAGE WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ? AND REFERENCED_TABLE_NAME = ?",
+                )
+                .bind(table)
+                .bind(column)
+                .bind(referenced_table)
+                .fetch_all(&self.pool)
+                .await
+                .expect("Could not inspect schema")
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+                for name in names {
+                    self.execute(&format!(
+                        "ALTER TABLE {} DROP FOREIGN KEY `{}`",
+                        table, name
+                    ))
+                    .await;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MySqlStorage {
+    async fn migrate(&self) {
+        sqlx::query(
+            r"CREATE TABLE IF NOT EXISTS schema_version (
+            version INT UNSIGNED PRIMARY KEY NOT NULL,
+            description TEXT NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP)",
+        )
+        .execute(&self.pool)
+        .await
+        .expect("Could not create schema_version table");
+        let current_version: u32 =
+            sqlx::query("SELECT version FROM schema_version ORDER BY version DESC LIMIT 1")
+                .fetch_optional(&self.pool)
+                .await
+                .expect("Could not query schema version")
+                .map_or(0, |row| row.get("version"));
+        for migration in migrations::pending(MIGRATIONS, current_version) {
+            info!(
+                "Applying migration {}: {}",
+                migration.version, migration.description
+            );
+            //mysql commits ddl implicitly, so nothing here can be rolled back. The steps are idempotent instead and a failed migration is simply run again
+            for step in migration.steps {
+                self.apply_step(step).await;
+            }
+            sqlx::query("INSERT INTO schema_version (version, description) VALUES (?,?)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&self.pool)
+                .await
+                .expect("Could not record schema version");
+        }
+    }
+
+    async fn create_world(&self, world_id: &str) -> bool {
+        sqlx::query("INSERT INTO worlds (world_id) VALUES (?)")
+            .bind(world_id)
+            .execute(&self.pool)
+            .await
+            .is_ok()
+    }
+    async fn world_exists(&self, world_id: &str) -> bool {
+        sqlx::query("SELECT * FROM worlds WHERE world_id = ?")
+            .bind(world_id)
+            .fetch_one(&self.pool)
+            .await
+            .is_ok()
+    }
+    async fn load_world_rules(&self, world_id: &str) -> Option<WorldRules> {
+        let row = sqlx::query(
+            "SELECT pvp, keep_inventory, spawn_x, spawn_y FROM worlds WHERE world_id = ?",
+        )
+        .bind(world_id)
+        .fetch_optional(&self.pool)
+        .await
+        .expect("error querying database for world rules")?;
+        let mut rules = WorldRules::new();
+        rules.pvp = row.try_get("pvp").expect("Could not get pvp");
+        rules.keep_inventory = row
+            .try_get("keep_inventory")
+            .expect("Could not get keep_inventory");
+        rules.spawn_point = (
+            row.try_get("spawn_x").expect("Could not get spawn_x"),
+            row.try_get("spawn_y").expect("Could not get spawn_y"),
+        );
+        Some(rules)
+    }
+    async fn save_world_rules(&self, world_id: &str, rules: &WorldRules) {
+        sqlx::query(
+            "UPDATE worlds SET pvp = ?, keep_inventory = ?, spawn_x = ?, spawn_y = ? WHERE world_id = ?",
+        )
+        .bind(rules.pvp)
+        .bind(rules.keep_inventory)
+        .bind(rules.spawn_point.0)
+        .bind(rules.spawn_point.1)
+        .bind(world_id)
+        .execute(&self.pool)
+        .await
+        .expect("Could not update world rules");
+    }
+
+    async fn chunk_exists(&self, world_id: &str, chunk_id: ChunkId) -> bool {
+        sqlx::query("SELECT chunk_id FROM chunks WHERE chunks.chunk_id = ? AND chunks.world_id = ?")
+            .bind(chunk_id.id())
+            .bind(world_id)
+            .fetch_one(&self.pool)
+            .await
+            .is_ok()
+    }
+    async fn load_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Option<Vec<u8>> {
+        let row = sqlx::query("SELECT chunk_dat FROM chunks WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id())
+            .bind(world_id)
+            .fetch_optional(&self.pool)
+            .await
+            .expect("error querying database for chunk")?;
+        sqlx::query("UPDATE chunks SET loaded = true WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id())
+            .bind(world_id)
+            .execute(&self.pool)
+            .await
+            .expect("error updating chunk");
+        Some(
+            row.try_get("chunk_dat")
+                .expect("chunk format in database invalid"),
+        )
+    }
+    async fn loaded_chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        sqlx::query("SELECT chunk_id FROM chunks WHERE loaded = true AND world_id = ?")
+            .bind(world_id)
+            .fetch_all(&self.pool)
+            .await
+            .expect("Error in database when loading chunks previously set as loaded")
+            .iter()
+            .map(|row| ChunkId::new_raw(row.try_get("chunk_id").expect("Could not get chunk_id")))
+            .collect()
+    }
+    async fn chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        sqlx::query("SELECT chunk_id FROM chunks WHERE world_id = ?")
+            .bind(world_id)
+            .fetch_all(&self.pool)
+            .await
+            .expect("Error in database when listing chunks")
+            .iter()
+            .map(|row| ChunkId::new_raw(row.try_get("chunk_id").expect("Could not get chunk_id")))
+            .collect()
+    }
+
+    async fn entities_in_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Vec<EntityId> {
+        sqlx::query("SELECT entity_id FROM entities WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id())
+            .bind(world_id)
+            .fetch_all(&self.pool)
+            .await
+            .expect("error querying database for entities")
+            .iter()
+            .map(|row| EntityId::new_with_number(row.try_get("entity_id").unwrap()))
+            .collect()
+    }
+    async fn load_components(&self, entity_id: EntityId) -> Vec<ComponentRecord> {
+        sqlx::query("SELECT type_id, dat FROM components WHERE entity_id = ?")
+            .bind(entity_id.id())
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| ComponentRecord {
+                type_name: row.try_get("type_id").expect("Could not query type_id"),
+                dat: row.try_get("dat").unwrap(),
+            })
+            .collect()
+    }
+    async fn save_world(&self, world_id: &str, save: &WorldSave) {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .expect("Could not begin transaction");
+        for batch in save.chunks.chunks(BATCH_SIZE) {
+            QueryBuilder::new("INSERT INTO chunks (chunk_id, world_id, chunk_dat, loaded) ")
+                .push_values(batch, |mut row, chunk| {
+                    row.push_bind(chunk.chunk_id.id())
+                        .push_bind(world_id)
+                        .push_bind(&chunk.chunk_dat)
+                        .push_bind(chunk.loaded);
+                })
+                .push(" ON DUPLICATE KEY UPDATE chunk_dat = VALUES(chunk_dat), loaded = VALUES(loaded)")
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not save chunks");
+        }
+        for batch in save.entities.chunks(BATCH_SIZE) {
+            QueryBuilder::new("INSERT INTO entities (entity_id, chunk_id, world_id) ")
+                .push_values(batch, |mut row, entity| {
+                    row.push_bind(entity.entity_id.id())
+                        .push_bind(entity.chunk_id.map(|c| c.id()))
+                        .push_bind(world_id);
+                })
+                .push(" ON DUPLICATE KEY UPDATE chunk_id = VALUES(chunk_id)")
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not save entities");
+        }
+        let components: Vec<(EntityId, &ComponentRecord)> = save
+            .entities
+            .iter()
+            .flat_map(|e| e.components.iter().map(move |c| (e.entity_id, c)))
+            .collect();
+        for batch in components.chunks(BATCH_SIZE) {
+            QueryBuilder::new("REPLACE INTO components (type_id, dat, entity_id) ")
+                .push_values(batch, |mut row, (entity_id, component)| {
+                    row.push_bind(&component.type_name)
+                        .push_bind(&component.dat)
+                        .push_bind(entity_id.id());
+                })
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not save components");
+        }
+        let removed: Vec<(EntityId, &String)> = save
+            .entities
+            .iter()
+            .flat_map(|e| e.removed_components.iter().map(move |c| (e.entity_id, c)))
+            .collect();
+        for batch in removed.chunks(BATCH_SIZE) {
+            QueryBuilder::new("DELETE FROM components WHERE (entity_id, type_id) IN ")
+                .push_tuples(batch, |mut row, (entity_id, type_name)| {
+                    row.push_bind(entity_id.id()).push_bind(*type_name);
+                })
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not delete removed components");
+        }
+        tx.commit().await.expect("Could not commit world save");
+    }
+    async fn delete_entity(&self, entity_id: EntityId) {
+        sqlx::query("DELETE FROM entities WHERE entities.entity_id = ?")
+            .bind(entity_id.id())
+            .execute(&self.pool)
+            .await
+            .expect("Could not delete entity from table");
+    }
+
+    async fn find_player(&self, world_id: &str, username: &str) -> Option<EntityId> {
+        sqlx::query("SELECT entities.entity_id FROM players JOIN entities ON players.entity_id = entities.entity_id JOIN users ON players.user_id = users.user_id WHERE users.user_name = ? AND entities.world_id = ?")
+            .bind(username)
+            .bind(world_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+            .map(|row| EntityId::new_with_number(row.get("entity_id")))
+    }
+    async fn add_player_to_user(&self, username: &str, entity_id: EntityId) {
+        sqlx::query("INSERT INTO players (user_id,entity_id) VALUES ((SELECT user_id FROM users WHERE user_name = ?),?)")
+            .bind(username)
+            .bind(entity_id.id())
+            .execute(&self.pool)
+            .await
+            .expect("could not spawn player");
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str, is_admin: bool) -> bool {
+        //the unique index on user_name makes a racing registration insert nothing instead of failing
+        sqlx::query("INSERT IGNORE INTO users (user_name, password_hash, admin) VALUES (?,?, ?)")
+            .bind(username)
+            .bind(password_hash)
+            .bind(is_admin)
+            .execute(&self.pool)
+            .await
+            .unwrap()
+            .rows_affected()
+            == 1
+    }
+    async fn get_user(&self, username: &str) -> Option<UserRecord> {
+        sqlx::query(
+            "SELECT password_hash, admin, invite_quota, banned FROM users WHERE user_name = ?",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+        .unwrap()
+        .map(|row| UserRecord {
+            user_name: username.to_owned(),
+            password_hash: row.try_get("password_hash").unwrap(),
+            is_admin: row.try_get("admin").unwrap(),
+            invite_quota: row.try_get("invite_quota").unwrap(),
+            banned: row.try_get("banned").unwrap(),
+        })
+    }
+    async fn set_invite_quota(&self, username: &str, quota: u32) -> bool {
+        sqlx::query("UPDATE users SET invite_quota = ? WHERE user_name = ?")
+            .bind(quota)
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .unwrap()
+            .rows_affected()
+            > 0
+    }
+    async fn set_banned(&self, username: &str, banned: bool) -> bool {
+        sqlx::query("UPDATE users SET banned = ? WHERE user_name = ?")
+            .bind(banned)
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .unwrap()
+            .rows_affected()
+            > 0
+    }
+
+    async fn create_invite_code(
+        &self,
+        username: &str,
+        code: &str,
+        uses: u32,
+        expires_at: u64,
+        default_quota: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .expect("Could not begin transaction");
+        //lock the user row so concurrent requests can't both slip under the quota
+        let row =
+            sqlx::query("SELECT user_id, invite_quota FROM users WHERE user_name = ? FOR UPDATE")
+                .bind(username)
+                .fetch_optional(&mut tx)
+                .await
+                .unwrap();
+        let (user_id, quota): (i32, Option<u32>) = match row {
+            Some(r) => (
+                r.try_get("user_id").unwrap(),
+                r.try_get("invite_quota").unwrap(),
+            ),
+            None => return Err(ErrorCode::UserNotFound),
+        };
+        if let Some(default_quota) = default_quota {
+            let issued: i64 =
+                sqlx::query("SELECT COUNT(*) AS issued FROM invite_codes WHERE created_by = ?")
+                    .bind(user_id)
+                    .fetch_one(&mut tx)
+                    .await
+                    .unwrap()
+                    .try_get("issued")
+                    .unwrap();
+            if issued >= quota.unwrap_or(default_quota) as i64 {
+                return Err(ErrorCode::InviteQuotaReached);
+            }
+        }
+        sqlx::query(
+            "INSERT INTO invite_codes (code, created_by, uses_remaining, expires_at) VALUES (?,?,?,?)",
+        )
+        .bind(code)
+        .bind(user_id)
+        .bind(uses)
+        .bind(expires_at)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+        tx.commit().await.expect("Could not commit invite code");
+        Ok(())
+    }
+    async fn register_user_with_invite_code(
+        &self,
+        username: &str,
+        password_hash: &str,
+        invite_code: &str,
+        now: u64,
+    ) -> Result<(), ErrorCode> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .expect("Could not begin transaction");
+        let row = sqlx::query(
+            "SELECT uses_remaining, expires_at FROM invite_codes WHERE code = ? FOR UPDATE",
+        )
+        .bind(invite_code)
+        .fetch_optional(&mut tx)
+        .await
+        .unwrap();
+        match row {
+            Some(r) => {
+                let uses_remaining: u32 = r.try_get("uses_remaining").unwrap();
+                let expires_at: u64 = r.try_get("expires_at").unwrap();
+                if uses_remaining == 0 || expires_at <= now {
+                    return Err(ErrorCode::InvalidInviteCode);
+                }
+            }
+            None => return Err(ErrorCode::InvalidInviteCode),
+        }
+        //a concurrent registration of the same name makes this insert nothing, and the invite keeps its use
+        let inserted = sqlx::query(
+            "INSERT IGNORE INTO users (user_name, password_hash, admin) VALUES (?,?, ?)",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(false)
+        .execute(&mut tx)
+        .await
+        .unwrap()
+        .rows_affected();
+        if inserted != 1 {
+            tx.rollback()
+                .await
+                .expect("Could not roll back registration");
+            return Err(ErrorCode::AlreadyExists);
+        }
+        sqlx::query("UPDATE invite_codes SET uses_remaining = uses_remaining - 1 WHERE code = ?")
+            .bind(invite_code)
+            .execute(&mut tx)
+            .await
+            .unwrap();
+        tx.commit().await.expect("Could not commit registration");
+        Ok(())
+    }
+
+    async fn create_session(&self, session: &SessionRecord) {
+        sqlx::query(
+            "INSERT INTO sessions (session_id, user_id, refresh_hash, expires_at, revoked) VALUES (?,(SELECT user_id FROM users WHERE user_name = ?),?,?,?)",
+        )
+        .bind(&session.session_id)
+        .bind(&session.user_name)
+        .bind(&session.refresh_hash)
+        .bind(session.expires_at)
+        .bind(session.revoked)
+        .execute(&self.pool)
+        .await
+        .expect("Could not create session");
+    }
+    async fn get_session(&self, session_id: &str) -> Option<SessionRecord> {
+        sqlx::query("SELECT sessions.refresh_hash, sessions.expires_at, sessions.revoked, users.user_name FROM sessions JOIN users ON sessions.user_id = users.user_id WHERE sessions.session_id = ?")
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+            .map(|row| SessionRecord {
+                session_id: session_id.to_owned(),
+                user_name: row.try_get("user_name").unwrap(),
+                refresh_hash: row.try_get("refresh_hash").unwrap(),
+                expires_at: row.try_get("expires_at").unwrap(),
+                revoked: row.try_get("revoked").unwrap(),
+            })
+    }
+    async fn rotate_session_refresh_hash(
+        &self,
+        session_id: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> bool {
+        sqlx::query("UPDATE sessions SET refresh_hash = ? WHERE session_id = ? AND refresh_hash = ? AND revoked = FALSE")
+            .bind(new_hash)
+            .bind(session_id)
+            .bind(old_hash)
+            .execute(&self.pool)
+            .await
+            .expect("Could not rotate refresh token")
+            .rows_affected()
+            == 1
+    }
+    async fn revoke_session(&self, session_id: &str) {
+        sqlx::query("UPDATE sessions SET revoked = TRUE WHERE session_id = ?")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .expect("Could not revoke session");
+    }
+    async fn active_session_ids(&self, username: &str) -> Vec<String> {
+        sqlx::query("SELECT sessions.session_id FROM sessions JOIN users ON sessions.user_id = users.user_id WHERE users.user_name = ? AND sessions.revoked = FALSE")
+            .bind(username)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.try_get("session_id").unwrap())
+            .collect()
+    }
+    async fn revoked_session_ids(&self, now: u64) -> Vec<String> {
+        sqlx::query("SELECT session_id FROM sessions WHERE revoked = TRUE AND expires_at > ?")
+            .bind(now)
+            .fetch_all(&self.pool)
+            .await
+            .expect("Could not load revoked sessions")
+            .iter()
+            .map(|row| row.try_get("session_id").unwrap())
+            .collect()
+    }
+}
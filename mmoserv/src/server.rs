@@ -1,15 +1,25 @@
 use crate::args;
+use crate::connection::{self, ConnectionOptions, Outbound};
 use crate::game;
+use crate::metered_storage::MeteredStorage;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::server_request;
 use crate::server_request::ServerClaims;
 use crate::server_request::ServerRequest;
-use crate::sql_loaders;
+use crate::shutdown::{self, ShutdownPhase};
+use crate::storage;
+use crate::storage::{SessionRecord, Storage};
 use futures::task::noop_waker;
+use mmolib::codec::{Codec, CodecError};
+use mmolib::component::ComponentTypeInfo;
+use mmolib::protocol::{self, HandshakeError};
 use mmolib::server_request_type::ServerRequestType;
-use mmolib::server_response_type::ServerResponseType;
-use sqlx::mysql::MySqlConnectOptions;
-use sqlx::ConnectOptions;
+use mmolib::server_response_type::{ErrorCode, ServerMessage, ServerResponseType};
+use mmolib::world_serializer::WorldSnapshot;
 use tokio::runtime::Handle;
 use tokio::sync::RwLock;
+use tokio::sync::{mpsc, watch};
 //use tokio::prelude::*;
 use bcrypt::bcrypt;
 use crossbeam_channel::internal::SelectHandle;
@@ -17,6 +27,8 @@ use crossbeam_channel::Receiver;
 use crossbeam_channel::Sender;
 use jsonwebtoken::TokenData;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::Rng;
+use std::time::Duration;
 use tokio::task;
 
 use futures::prelude::*;
@@ -24,14 +36,8 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde_json::json;
 use serde_json::Value;
-use sqlx::mysql::MySqlPoolOptions;
-use sqlx::mysql::MySqlRow;
-use sqlx::pool::PoolConnection;
-use sqlx::Acquire;
-use sqlx::MySql;
-use sqlx::Pool;
-use sqlx::Row;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::io::Read;
 use std::io::Write;
 use std::sync::Arc;
@@ -43,6 +49,7 @@ use tokio::net::TcpStream;
 use tokio_tungstenite::tungstenite::http::request;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
+use tracing::error;
 use tracing::event;
 use tracing::info;
 use tracing::span;
@@ -52,36 +59,92 @@ use tracing::Level;
 
 pub struct Server {
     visibility: args::RegistrationPolicy,
-    dburl: String,
-    pool: Pool<MySql>,
+    storage: Arc<dyn Storage>,
     game: HashMap<String, Arc<RwLock<game::Game>>>,
     key: String,
     listen_url: String,
     open_streams: Vec<Arc<RwLock<WebSocketStream<TcpStream>>>>,
+    invite_quota: u32,
+    invite_code_uses: u32,
+    invite_code_lifetime: u64,
+    session_lifetime: u64,
+    refresh_lifetime: u64,
+    revoked_sessions: HashSet<String>,
+    component_types: Arc<Vec<ComponentTypeInfo>>,
+    tick_rate: u32,
+    raws_path: String,
+    metrics: Arc<Metrics>,
+    metrics_url: String,
+    shutdown: watch::Sender<ShutdownPhase>,
+    game_tasks: Vec<(String, task::JoinHandle<()>)>,
+}
+
+fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
 
 impl Server {
     pub async fn create_user(&self, username: &str, password: &str, is_admin: bool) -> bool {
         let pass = bcrypt::hash_with_result(password, 6).expect("Could not hash password");
-        if !self.user_exists(username).await {
-            sqlx::query("INSERT INTO users (user_name, password_hash, admin) VALUES (?,?, ?)")
-                .bind(username)
-                .bind(pass.to_string())
-                .bind(is_admin)
-                .execute(&self.pool)
-                .await
-                .unwrap();
-            return true;
-        }
-        false
+        self.storage
+            .create_user(username, &pass.to_string(), is_admin)
+            .await
     }
     pub async fn user_exists(&self, username: &str) -> bool {
-        let x = sqlx::query("SELECT admin FROM users WHERE user_name = ?")
-            .bind(username)
-            .fetch_one(&self.pool)
-            .await;
-        let res = !x.is_err();
-        res
+        self.storage.get_user(username).await.is_some()
+    }
+    /**
+     * Creates an invite code for a user, counting it against their quota. Admins have no quota.
+     */
+    pub async fn create_invite_code(
+        &self,
+        username: &str,
+        is_admin: bool,
+    ) -> Result<(String, u64), ErrorCode> {
+        let code = random_token(16);
+        let expires_at = unix_timestamp() + self.invite_code_lifetime;
+        let quota = if is_admin {
+            None
+        } else {
+            Some(self.invite_quota)
+        };
+        self.storage
+            .create_invite_code(username, &code, self.invite_code_uses, expires_at, quota)
+            .await?;
+        Ok((code, expires_at))
+    }
+    /**
+     * Registers a user, consuming one use of the invite code in the same transaction.
+     */
+    pub async fn create_user_with_invite_code(
+        &self,
+        username: &str,
+        password: &str,
+        invite_code: &str,
+    ) -> Result<(), ErrorCode> {
+        let pass = bcrypt::hash_with_result(password, 6).expect("Could not hash password");
+        self.storage
+            .register_user_with_invite_code(
+                username,
+                &pass.to_string(),
+                invite_code,
+                unix_timestamp(),
+            )
+            .await
+    }
+    pub async fn set_invite_quota(&self, username: &str, quota: u32) -> bool {
+        self.storage.set_invite_quota(username, quota).await
     }
     pub fn get_claims(&self, session: &str) -> Option<ServerClaims> {
         let token = decode::<ServerClaims>(
@@ -97,42 +160,26 @@ impl Server {
             }
         }
     }
-    pub async fn generate_session(&self, username: &str, password: &str) -> Option<String> {
-        let mut user: Option<(String, bool)> = None;
-        let row = sqlx::query("SELECT password_hash, admin FROM users WHERE user_name = ?")
-            .bind(username)
-            .fetch_optional(&self.pool)
-            .await
-            .unwrap();
-        match row {
-            Some(r) => {
-                let u: &str = r.try_get("password_hash").unwrap();
-                let admin: bool = r.try_get("admin").unwrap();
-                user = Some((u.to_owned(), admin));
-            }
-            None => {
-                info!("Tried to login as user {} which does not exist", username);
-            }
+    /**
+     * Checks a user's password and starts a new session, returning the session and refresh tokens.
+     */
+    pub async fn generate_session(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Option<(String, String)> {
+        let user = self.storage.get_user(username).await;
+        if user.is_none() {
+            info!("Tried to login as user {} which does not exist", username);
         }
         match user {
-            Some((u, is_admin)) => match bcrypt::verify(password, &u) {
+            Some(u) if u.banned => {
+                info!("Banned user {} tried to login", username);
+            }
+            Some(u) => match bcrypt::verify(password, &u.password_hash) {
                 Ok(b) => {
                     if b {
-                        let claims = ServerClaims {
-                            user_name: String::from(username),
-                            is_admin: is_admin,
-                            exp: 10000000000,
-                        };
-                        match encode(
-                            &Header::default(),
-                            &claims,
-                            &EncodingKey::from_secret("secret".as_ref()),
-                        ) {
-                            Ok(tok) => return Some(tok),
-                            Err(e) => {
-                                return None;
-                            }
-                        }
+                        return self.create_session(username, u.is_admin).await;
                     }
                 }
 
@@ -146,51 +193,304 @@ impl Server {
         }
         None
     }
-    async fn listen_thread(listener: TcpListener, sv: Arc<RwLock<Self>>) {
+    fn sign_session_token(
+        &self,
+        username: &str,
+        is_admin: bool,
+        session_id: &str,
+    ) -> Option<String> {
+        let claims = ServerClaims {
+            user_name: String::from(username),
+            is_admin: is_admin,
+            sid: session_id.to_owned(),
+            exp: (unix_timestamp() + self.session_lifetime) as usize,
+        };
+        match encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(self.key.as_ref()),
+        ) {
+            Ok(tok) => Some(tok),
+            Err(e) => {
+                warn!("Could not sign session token {}", e);
+                None
+            }
+        }
+    }
+    /**
+     * Refresh tokens are "session_id.secret", only a hash of the secret is stored.
+     */
+    async fn create_session(&self, username: &str, is_admin: bool) -> Option<(String, String)> {
+        let session_id = random_token(24);
+        let secret = random_token(32);
+        let refresh_hash = bcrypt::hash_with_result(&secret, 6).expect("Could not hash token");
+        self.storage
+            .create_session(&SessionRecord {
+                session_id: session_id.clone(),
+                user_name: username.to_owned(),
+                refresh_hash: refresh_hash.to_string(),
+                expires_at: unix_timestamp() + self.refresh_lifetime,
+                revoked: false,
+            })
+            .await;
+        let token = self.sign_session_token(username, is_admin, &session_id)?;
+        Some((token, format!("{}.{}", session_id, secret)))
+    }
+    /**
+     * Exchanges a refresh token for a new session token. The refresh token is rotated, so each one can only be used once.
+     * Presenting a token that was already rotated means it leaked, so the whole session is revoked.
+     */
+    pub async fn refresh_session(&mut self, refresh_token: &str) -> Option<(String, String)> {
+        let (session_id, secret) = refresh_token.split_once('.')?;
+        if self.is_session_revoked(session_id) {
+            return None;
+        }
+        let session = self.storage.get_session(session_id).await?;
+        if session.revoked || session.expires_at <= unix_timestamp() {
+            return None;
+        }
+        if !bcrypt::verify(secret, &session.refresh_hash).unwrap_or(false) {
+            info!(
+                "Reused refresh token for session {}, revoking it",
+                session_id
+            );
+            self.revoke_session(session_id).await;
+            return None;
+        }
+        let user = self.storage.get_user(&session.user_name).await?;
+        if user.banned {
+            return None;
+        }
+        let is_admin = user.is_admin;
+        let new_secret = random_token(32);
+        let new_hash = bcrypt::hash_with_result(&new_secret, 6).expect("Could not hash token");
+        if !self
+            .storage
+            .rotate_session_refresh_hash(session_id, &session.refresh_hash, &new_hash.to_string())
+            .await
+        {
+            info!(
+                "Refresh token for session {} was already used, revoking it",
+                session_id
+            );
+            self.revoke_session(session_id).await;
+            return None;
+        }
+        let token = self.sign_session_token(&session.user_name, is_admin, session_id)?;
+        Some((token, format!("{}.{}", session_id, new_secret)))
+    }
+    pub async fn revoke_session(&mut self, session_id: &str) {
+        self.storage.revoke_session(session_id).await;
+        self.revoked_sessions.insert(session_id.to_owned());
+    }
+    /**
+     * Revokes every session of a user, returns false if the user doesn't exist.
+     */
+    pub async fn revoke_user_sessions(&mut self, username: &str) -> bool {
+        if !self.user_exists(username).await {
+            return false;
+        }
+        for session_id in self.storage.active_session_ids(username).await {
+            self.revoke_session(&session_id).await;
+        }
+        true
+    }
+    pub fn is_session_revoked(&self, session_id: &str) -> bool {
+        self.revoked_sessions.contains(session_id)
+    }
+    async fn load_revoked_sessions(&mut self) {
+        let revoked = self.storage.revoked_session_ids(unix_timestamp()).await;
+        self.revoked_sessions.extend(revoked);
+    }
+    /**
+     * Text frames are decoded as json and binary frames as cbor, whatever codec the connection negotiated for responses.
+     */
+    fn decode_message(msg: &Message) -> Option<Result<Value, CodecError>> {
+        match msg {
+            Message::Text(txt) => Some(Codec::Json.decode(txt.as_bytes())),
+            Message::Binary(bytes) => Some(Codec::Cbor.decode(bytes)),
+            _ => None,
+        }
+    }
+    /**
+     * Tells an incompatible client why it was refused, always as json since no codec was agreed on, then closes the socket.
+     */
+    async fn reject_handshake(outbound: &Outbound, request_id: Option<u64>, error: HandshakeError) {
+        event!(Level::INFO, "Rejected client handshake: {:?}", error);
+        let response = ServerResponseType::HandshakeRejected {
+            error: error,
+            server_protocol_version: protocol::PROTOCOL_VERSION,
+            min_protocol_version: protocol::MIN_PROTOCOL_VERSION,
+        };
+        Self::send_reply(outbound, Codec::Json, request_id, response).await;
+        outbound.close().await;
+    }
+    /**
+     * Replies to messages that never became a ServerRequest.
+     */
+    async fn send_reply(
+        outbound: &Outbound,
+        codec: Codec,
+        request_id: Option<u64>,
+        response: ServerResponseType,
+    ) {
+        let message = ServerMessage::Reply {
+            request_id: request_id,
+            response: response,
+        };
+        outbound
+            .send(connection::encode_message(codec, &message))
+            .await;
+    }
+    async fn send_push(outbound: &Outbound, codec: Codec, response: ServerResponseType) {
+        outbound
+            .send(connection::encode_message(
+                codec,
+                &ServerMessage::Push { response: response },
+            ))
+            .await;
+    }
+    /**
+     * Accepts connections until the future is dropped. Every connection task holds a clone of done, so the receiver sees when they have all closed.
+     */
+    async fn listen_thread(listener: TcpListener, sv: Arc<RwLock<Self>>, done: mpsc::Sender<()>) {
         let span = span!(Level::INFO, "server_listen_thread");
         let _guard = span.enter();
         let lk = sv.read().await;
         let key = lk.key.clone();
+        let component_types = lk.component_types.clone();
+        let shutdown = lk.shutdown.subscribe();
         drop(lk);
         loop {
             for (mut conn, addr) in listener.accept().await {
                 //spawn a worker thread
                 let svnew = sv.clone();
                 let key = key.clone();
+                let component_types = component_types.clone();
+                let mut shutdown = shutdown.clone();
+                let done = done.clone();
                 task::spawn(async move {
                     let (wsw, mut wsr) = tokio_tungstenite::accept_async(conn)
                         .await
                         //todo: handle error if weird non ws connection
                         .expect("Could not listen on the websocket connection")
                         .split();
-                    //there can be multiple connection senders, but only one reader. That's why ws write (wsw) is in an arc.
-                    let mut wsw = Arc::new(RwLock::new(wsw));
+                    //replies and pushes from every world share this queue, so they reach the client in order
+                    let outbound = Outbound::new(wsw);
+                    //responses are json until the client negotiates another codec in its hello.
+                    let mut options = ConnectionOptions::default();
+                    let mut welcomed = false;
                     loop {
                         //loop until connection is terminated
-                        match wsr.next().await {
+                        let next = tokio::select! {
+                            next = wsr.next() => next,
+                            _ = shutdown::wait_for_phase(&mut shutdown, ShutdownPhase::Draining) => {
+                                //stop taking requests, but keep the socket open so the final tick still arrives
+                                Self::send_push(&outbound, options.codec, ServerResponseType::ServerShutdown {}).await;
+                                shutdown::wait_for_phase(&mut shutdown, ShutdownPhase::Closing).await;
+                                outbound.close().await;
+                                break;
+                            }
+                        };
+                        match next {
                             Some(msg) => match msg {
-                                Ok(msg) => match msg.to_text() {
-                                    Ok(txt) => match serde_json::from_str(txt) {
-                                        Ok(json_value) => {
-                                            match ServerRequest::new(
-                                                json_value,
-                                                &key.clone(),
-                                                wsw.clone(),
-                                            ) {
-                                                Ok(request) => {
+                                Ok(msg) => match Self::decode_message(&msg) {
+                                    Some(Ok(json_value)) => {
+                                        let request_id =
+                                            server_request::get_request_id(&json_value);
+                                        match ServerRequest::new(
+                                            json_value,
+                                            &key.clone(),
+                                            outbound.clone(),
+                                            options.clone(),
+                                        ) {
+                                            Ok(mut request) => {
+                                                if let ServerRequestType::Hello {
+                                                    protocol_version,
+                                                    codec: requested,
+                                                    features,
+                                                } = request.get_dat()
+                                                {
+                                                    if let Err(e) = protocol::check_protocol_version(
+                                                        *protocol_version,
+                                                    ) {
+                                                        Self::reject_handshake(
+                                                            &outbound, request_id, e,
+                                                        )
+                                                        .await;
+                                                        break;
+                                                    }
+                                                    options = ConnectionOptions {
+                                                        codec: *requested,
+                                                        features: protocol::negotiate_features(
+                                                            features,
+                                                        ),
+                                                    };
+                                                    let welcome = ServerResponseType::Welcome {
+                                                        protocol_version:
+                                                            protocol::PROTOCOL_VERSION,
+                                                        codec: options.codec,
+                                                        component_types: component_types.to_vec(),
+                                                        features: options.features.clone(),
+                                                    };
+                                                    welcomed = true;
+                                                    request.set_options(options.clone());
+                                                    request.handle(welcome).await;
+                                                } else if welcomed {
                                                     Self::worker_thread(request, svnew.clone())
                                                         .await;
-                                                }
-                                                Err(_) => {
-                                                    event!(Level::INFO, "Client sent valid json but invalid request");
+                                                } else {
+                                                    Self::reject_handshake(
+                                                        &outbound,
+                                                        request_id,
+                                                        HandshakeError::HelloRequired,
+                                                    )
+                                                    .await;
+                                                    break;
                                                 }
                                             }
+                                            Err(_) if !welcomed => {
+                                                //most likely a client from before the handshake existed
+                                                Self::reject_handshake(
+                                                    &outbound,
+                                                    request_id,
+                                                    HandshakeError::HelloRequired,
+                                                )
+                                                .await;
+                                                break;
+                                            }
+                                            Err(e) => {
+                                                event!(
+                                                    Level::INFO,
+                                                    "Client sent valid json but invalid request"
+                                                );
+                                                Self::send_reply(
+                                                    &outbound,
+                                                    options.codec,
+                                                    request_id,
+                                                    ServerResponseType::error_with_detail(
+                                                        ErrorCode::MalformedRequest,
+                                                        &e.to_string(),
+                                                    ),
+                                                )
+                                                .await;
+                                            }
                                         }
-                                        Err(_) => {
-                                            event!(Level::INFO, "Client send invalid json");
-                                        }
-                                    },
-                                    Err(_) => {
+                                    }
+                                    Some(Err(e)) => {
+                                        event!(Level::INFO, "Client send invalid json");
+                                        Self::send_reply(
+                                            &outbound,
+                                            options.codec,
+                                            None,
+                                            ServerResponseType::error_with_detail(
+                                                ErrorCode::MalformedRequest,
+                                                &e.to_string(),
+                                            ),
+                                        )
+                                        .await;
+                                    }
+                                    None => {
                                         event!(
                                             Level::INFO,
                                             "Client sent invalid websocket message type"
@@ -199,54 +499,84 @@ impl Server {
                                 },
                                 Err(_) => {
                                     event!(Level::INFO, "Client closed a connection");
-                                    wsw.write().await.close().await;
+                                    outbound.close().await;
                                     break;
                                 }
                             },
                             None => {
                                 event!(Level::INFO, "Could not await the next message from reader, closing connection");
-                                wsw.write().await.close().await;
+                                outbound.close().await;
                                 break;
                             }
                         }
                     }
+                    //the server waits on done until every connection task has finished
+                    drop(done);
                 });
             }
         }
     }
+    fn new_game(&self, world_name: &str) -> game::Game {
+        game::Game::new(
+            &self.raws_path,
+            self.storage.clone(),
+            world_name.to_owned(),
+            self.tick_rate,
+            self.metrics.clone(),
+        )
+    }
     pub async fn create_world(&mut self, world_name: &str) -> bool {
-        if sql_loaders::create_world(self.pool.clone(), world_name).await {
-            let g = game::Game::new(
-                "C:\\Users\\justin.suess\\Code\\mmo\\raws",
-                self.pool.clone(),
-                world_name.to_owned(),
-            );
+        if self.storage.create_world(world_name).await {
+            let g = self.new_game(world_name);
             //insert the world into the database
             let gmrwlock = Arc::new(RwLock::new(g));
-            let gmrwlock2 = gmrwlock.clone();
-            game::Game::start_game(gmrwlock).await;
-            self.game.insert(String::from(world_name), gmrwlock2);
+            self.start_game(world_name, gmrwlock).await;
             true
         } else {
             false
         }
     }
     pub async fn load_world(&mut self, world_name: &str) -> bool {
-        if sql_loaders::check_if_world_exists(self.pool.clone(), world_name).await {
-            let g = game::Game::new(
-                "C:\\Users\\justin.suess\\Code\\mmo\\raws",
-                self.pool.clone(),
-                world_name.to_owned(),
-            );
+        if self.storage.world_exists(world_name).await {
+            let g = self.new_game(world_name);
             //insert the world into the database
             let gmrwlock = Arc::new(RwLock::new(g));
-            let gmrwlock2 = gmrwlock.clone();
-            game::Game::start_game(gmrwlock).await;
-            self.game.insert(String::from(world_name), gmrwlock2);
+            self.start_game(world_name, gmrwlock).await;
             return true;
         }
         false
     }
+    /**
+     * Creates a new world from a snapshot. The snapshot is checked before anything is written to storage.
+     */
+    pub async fn import_world(
+        &mut self,
+        world_name: &str,
+        snapshot: &WorldSnapshot,
+    ) -> Result<(), ErrorCode> {
+        if self.storage.world_exists(world_name).await {
+            return Err(ErrorCode::AlreadyExists);
+        }
+        let g = self.new_game(world_name);
+        if let Err(e) = g.import_snapshot(snapshot).await {
+            warn!("Could not import snapshot into {}: {:?}", world_name, e);
+            return Err(ErrorCode::InvalidSnapshot);
+        }
+        if !self.storage.create_world(world_name).await {
+            return Err(ErrorCode::AlreadyExists);
+        }
+        self.storage
+            .save_world_rules(world_name, &snapshot.metadata.rules)
+            .await;
+        let gmrwlock = Arc::new(RwLock::new(g));
+        self.start_game(world_name, gmrwlock).await;
+        Ok(())
+    }
+    async fn start_game(&mut self, world_name: &str, gm: Arc<RwLock<game::Game>>) {
+        let handle = game::Game::start_game(gm.clone(), self.shutdown.subscribe()).await;
+        self.game_tasks.push((world_name.to_owned(), handle));
+        self.game.insert(String::from(world_name), gm);
+    }
     async fn worker_thread(req: ServerRequest, sv: Arc<RwLock<Self>>) {
         let span = span!(
             Level::INFO,
@@ -255,50 +585,116 @@ impl Server {
         );
         let _guard = span.enter();
 
+        //tokens of revoked sessions are rejected even if they haven't expired yet
+        if let Some(session_id) = req.get_session_id() {
+            if sv.read().await.is_session_revoked(session_id) {
+                req.handle(ServerResponseType::AuthFailure {}).await;
+                return;
+            }
+        }
+
         match &req.get_dat() {
             ServerRequestType::CreateGame { world_name } => {
                 if req.is_admin() {
                     let mut guard = sv.write().await;
                     if guard.create_world(&world_name).await {
-                        req.handle(&ServerResponseType::Ok {}).await;
+                        req.handle(ServerResponseType::Ok {}).await;
                     } else {
-                        req.handle(&ServerResponseType::Error {
-                            message: "World already exists",
-                        })
-                        .await;
+                        req.handle(ServerResponseType::error(ErrorCode::AlreadyExists))
+                            .await;
                     }
                 } else {
-                    req.handle(&ServerResponseType::PermissionDenied {}).await;
+                    req.handle(ServerResponseType::PermissionDenied {}).await;
                 }
             }
             ServerRequestType::LoadGame { world_name } => {
                 if req.is_admin() {
                     let mut guard = sv.write().await;
                     if guard.load_world(&world_name).await {
-                        req.handle(&ServerResponseType::Ok {}).await;
+                        req.handle(ServerResponseType::Ok {}).await;
                     } else {
-                        req.handle(&ServerResponseType::Error {
-                            message: "World does not exist",
-                        })
-                        .await;
+                        req.handle(ServerResponseType::error(ErrorCode::WorldNotFound))
+                            .await;
+                    }
+                } else {
+                    req.handle(ServerResponseType::PermissionDenied {}).await;
+                }
+            }
+            ServerRequestType::ImportWorld {
+                world_name,
+                snapshot,
+            } => {
+                if req.is_admin() {
+                    let mut guard = sv.write().await;
+                    let result = guard.import_world(&world_name, &snapshot).await;
+                    drop(guard);
+                    match result {
+                        Ok(()) => {
+                            req.handle(ServerResponseType::Ok {}).await;
+                        }
+                        Err(code) => {
+                            req.handle(ServerResponseType::error(code)).await;
+                        }
                     }
                 } else {
-                    req.handle(&ServerResponseType::PermissionDenied {}).await;
+                    req.handle(ServerResponseType::PermissionDenied {}).await;
                 }
             }
             ServerRequestType::Login { user, password } => {
                 let guard = sv.read().await;
                 let x = guard.generate_session(&user, &password).await;
                 match x {
-                    Some(token) => {
-                        req.handle(&ServerResponseType::AuthSuccess {
+                    Some((token, refresh_token)) => {
+                        req.handle(ServerResponseType::AuthSuccess {
+                            session_token: token,
+                            refresh_token: refresh_token,
+                        })
+                        .await;
+                    }
+                    None => {
+                        req.handle(ServerResponseType::AuthFailure {}).await;
+                    }
+                }
+            }
+            ServerRequestType::RefreshSession { refresh_token } => {
+                let mut guard = sv.write().await;
+                let x = guard.refresh_session(&refresh_token).await;
+                drop(guard);
+                match x {
+                    Some((token, refresh_token)) => {
+                        req.handle(ServerResponseType::AuthSuccess {
                             session_token: token,
+                            refresh_token: refresh_token,
                         })
                         .await;
                     }
                     None => {
-                        req.handle(&ServerResponseType::AuthFailure {}).await;
+                        req.handle(ServerResponseType::AuthFailure {}).await;
+                    }
+                }
+            }
+            ServerRequestType::Logout {} => match req.get_session_id().map(str::to_string) {
+                Some(session_id) => {
+                    sv.write().await.revoke_session(&session_id).await;
+                    req.handle(ServerResponseType::Ok {}).await;
+                }
+                None => {
+                    req.handle(ServerResponseType::AuthFailure {}).await;
+                }
+            },
+            ServerRequestType::RevokeUserSessions { user } => {
+                if req.is_admin() {
+                    let mut guard = sv.write().await;
+                    if guard.revoke_user_sessions(&user).await {
+                        drop(guard);
+                        req.handle(ServerResponseType::Ok {}).await;
+                    } else {
+                        drop(guard);
+                        req.handle(ServerResponseType::error(ErrorCode::UserNotFound))
+                            .await;
                     }
+                } else {
+                    req.handle(ServerResponseType::PermissionDenied {}).await;
                 }
             }
             ServerRequestType::RegisterUser {
@@ -313,21 +709,82 @@ impl Server {
                     args::RegistrationPolicy::Public => {
                         let guard = sv.write().await;
                         if guard.create_user(&user, &password, false).await {
-                            req.handle(&ServerResponseType::Ok {}).await;
+                            req.handle(ServerResponseType::Ok {}).await;
                         } else {
-                            req.handle(&ServerResponseType::Error {
-                                message: "User already exists",
-                            })
-                            .await;
+                            req.handle(ServerResponseType::error(ErrorCode::AlreadyExists))
+                                .await;
                         }
                     }
                     args::RegistrationPolicy::Closed => {
-                        req.handle(&ServerResponseType::Error {
-                            message: "Server is closed for new registrations",
-                        })
-                        .await;
+                        req.handle(ServerResponseType::error(ErrorCode::RegistrationClosed))
+                            .await;
+                    }
+                    args::RegistrationPolicy::InviteOnly => match invite_code {
+                        Some(code) => {
+                            let guard = sv.read().await;
+                            match guard
+                                .create_user_with_invite_code(&user, &password, &code)
+                                .await
+                            {
+                                Ok(()) => {
+                                    req.handle(ServerResponseType::Ok {}).await;
+                                }
+                                Err(code) => {
+                                    req.handle(ServerResponseType::error(code)).await;
+                                }
+                            }
+                        }
+                        None => {
+                            req.handle(ServerResponseType::error(ErrorCode::InviteRequired))
+                                .await;
+                        }
+                    },
+                }
+            }
+            ServerRequestType::GetUserInviteCode {} => match req.get_user().map(str::to_string) {
+                Some(user) => {
+                    let guard = sv.read().await;
+                    match guard.visibility {
+                        args::RegistrationPolicy::InviteOnly => {
+                            match guard.create_invite_code(&user, req.is_admin()).await {
+                                Ok((code, expires_at)) => {
+                                    let uses = guard.invite_code_uses;
+                                    drop(guard);
+                                    req.handle(ServerResponseType::InviteCode {
+                                        invite_code: code,
+                                        uses: uses,
+                                        expires_at: expires_at,
+                                    })
+                                    .await;
+                                }
+                                Err(code) => {
+                                    drop(guard);
+                                    req.handle(ServerResponseType::error(code)).await;
+                                }
+                            }
+                        }
+                        _ => {
+                            drop(guard);
+                            req.handle(ServerResponseType::error(ErrorCode::NotInviteOnly))
+                                .await;
+                        }
+                    }
+                }
+                None => {
+                    req.handle(ServerResponseType::AuthFailure {}).await;
+                }
+            },
+            ServerRequestType::SetInviteQuota { user, quota } => {
+                if req.is_admin() {
+                    let guard = sv.read().await;
+                    if guard.set_invite_quota(&user, *quota).await {
+                        req.handle(ServerResponseType::Ok {}).await;
+                    } else {
+                        req.handle(ServerResponseType::error(ErrorCode::UserNotFound))
+                            .await;
                     }
-                    args::RegistrationPolicy::InviteOnly => todo!(),
+                } else {
+                    req.handle(ServerResponseType::PermissionDenied {}).await;
                 }
             }
             other => match req.get_world().map(str::to_string) {
@@ -340,17 +797,21 @@ impl Server {
                                 game::Game::handle(gmc, req).await;
                             }
                             None => {
-                                req.handle(&ServerResponseType::AuthFailure {}).await;
+                                req.handle(ServerResponseType::AuthFailure {}).await;
                                 trace!("User must be logged in to join {}", &world_name);
                             }
                         },
                         None => {
-                            req.handle(&ServerResponseType::AuthFailure {}).await;
+                            req.handle(ServerResponseType::error(ErrorCode::WorldNotFound))
+                                .await;
                             trace!("World {} doesn't exist yet", &world_name);
                         }
                     }
                 }
-                None => {}
+                None => {
+                    req.handle(ServerResponseType::error(ErrorCode::UnsupportedRequest))
+                        .await;
+                }
             },
         }
     }
@@ -358,36 +819,40 @@ impl Server {
         let (tx, rx) = crossbeam_channel::unbounded::<ServerRequest>();
 
         let key = args.secret.clone();
-        let dburl = format!(
-            "mysql://{}:{}@{}/{}",
-            args.database_user, args.database_pass, args.database_host, args.database_name
-        );
-        info!("Connecting to database at {}", dburl);
-        let mut opts = MySqlConnectOptions::new()
-            .host(&args.database_host)
-            .username(&args.database_user)
-            .database(&args.database_name)
-            .password(&args.database_pass);
-        opts.disable_statement_logging();
-
-        let mut pool = MySqlPoolOptions::new()
-            .max_connections(5)
-            .connect_with(opts)
-            .await
-            .expect("Could not get db conn");
-        info!("Database connection established");
+        let metrics = Arc::new(Metrics::new());
+        let storage = Arc::new(MeteredStorage::new(
+            storage::connect(args).await,
+            metrics.clone(),
+        ));
         Self {
-            dburl: dburl,
             listen_url: format!("{}:{}", args.ip, args.port),
-            pool: pool,
+            metrics_url: format!("{}:{}", args.ip, args.metrics_port),
+            storage: storage,
             game: HashMap::new(),
             key: key,
             open_streams: Vec::new(),
             visibility: args.server_visibility.clone(),
+            invite_quota: args.invite_quota,
+            invite_code_uses: args.invite_code_uses,
+            invite_code_lifetime: args.invite_code_lifetime_hours * 60 * 60,
+            session_lifetime: args.session_lifetime_minutes * 60,
+            refresh_lifetime: args.refresh_lifetime_hours * 60 * 60,
+            revoked_sessions: HashSet::new(),
+            component_types: Arc::new(
+                mmolib::registry::RegistryBuilder::new()
+                    .build()
+                    .component_types(),
+            ),
+            tick_rate: args.tick_rate,
+            raws_path: args.raws.clone(),
+            metrics: metrics,
+            shutdown: watch::channel(ShutdownPhase::Running).0,
+            game_tasks: Vec::new(),
         }
     }
-    pub async fn run_game(self) {
-        sql_loaders::initialize_database(self.pool.clone()).await;
+    pub async fn run_game(mut self) -> bool {
+        self.storage.migrate().await;
+        self.load_revoked_sessions().await;
         if !self.user_exists("admin").await {
             warn!("Creating user admin with default password \"password\"");
             self.create_user("admin", "password", true).await;
@@ -395,13 +860,116 @@ impl Server {
         let listener = TcpListener::bind(&self.listen_url)
             .await
             .expect("Could not bind to ip/port");
+        let metrics_listener = TcpListener::bind(&self.metrics_url)
+            .await
+            .expect("Could not bind metrics to ip/port");
+        task::spawn(metrics::serve(metrics_listener, self.metrics.clone()));
         //create server arc
         let sv = Arc::new(RwLock::new(self));
-        //create server listen thread
-        Self::listen_thread(listener, sv.clone()).await;
+        let (done, connections_closed) = mpsc::channel(1);
+        //the listener is dropped with the listen thread, so no connections are accepted once a signal arrives
+        let signal = tokio::select! {
+            _ = Self::listen_thread(listener, sv.clone(), done) => "listener closed",
+            signal = shutdown::wait_for_signal() => signal,
+        };
+        info!("Received {}, shutting down", signal);
+        Self::shutdown(sv, connections_closed).await
+    }
+    /**
+     * Lets every world finish its tick and save with its chunks marked unloaded, then closes every connection.
+     * Returns false if a world could not be saved.
+     */
+    async fn shutdown(sv: Arc<RwLock<Self>>, mut connections_closed: mpsc::Receiver<()>) -> bool {
+        let mut lk = sv.write().await;
+        lk.shutdown.send(ShutdownPhase::Draining);
+        let game_tasks = std::mem::take(&mut lk.game_tasks);
+        drop(lk);
+        let mut clean = true;
+        for (world_name, handle) in game_tasks {
+            match handle.await {
+                Ok(()) => info!("Saved world {}", world_name),
+                Err(e) => {
+                    error!("World {} stopped without saving: {}", world_name, e);
+                    clean = false;
+                }
+            }
+        }
+        sv.read().await.shutdown.send(ShutdownPhase::Closing);
+        if tokio::time::timeout(Duration::from_secs(5), connections_closed.recv())
+            .await
+            .is_err()
+        {
+            warn!("Some connections did not close in time");
+        }
+        clean
     }
 }
 
 fn decode_valid_requests(stream: &TcpStream) -> Option<ServerRequest> {
     None
 }
+
+#[cfg(test)]
+async fn test_server() -> Server {
+    use clap::Parser;
+    let args = args::Args::parse_from(["mmoserv", "--storage", "memory", "--raws", "../raws"]);
+    Server::new(&args).await
+}
+
+#[tokio::test]
+async fn test_shutdown_saves_worlds() {
+    use mmolib::chunk;
+    let mut server = test_server().await;
+    assert!(server.create_world("world").await);
+    let storage = server.storage.clone();
+    let gm = server.game.get("world").unwrap().clone();
+    let chunk_id = chunk::chunk_id_from_position((3, 3));
+    {
+        let lk = gm.read().await;
+        let stone = lk
+            .get_registry()
+            .get_block_type("stonefloor")
+            .unwrap()
+            .get_id();
+        let mut wlk = lk.get_world().lock().await;
+        wlk.insert_chunk((
+            chunk_id,
+            chunk::Chunk::new_from_array([[stone; chunk::CHUNK_SIZE]; chunk::CHUNK_SIZE]),
+        ));
+        wlk.spawn().insert(mmolib::position::Position {
+            pos: (3, 3),
+            load_with_chunk: true,
+        });
+        wlk.run_between_ticks_scheduler();
+    }
+    let sv = Arc::new(RwLock::new(server));
+    //no connections are open, so none need to close
+    let (done, connections_closed) = mpsc::channel(1);
+    drop(done);
+    let clean = tokio::time::timeout(
+        Duration::from_secs(10),
+        Server::shutdown(sv.clone(), connections_closed),
+    )
+    .await
+    .expect("The world did not finish draining");
+    assert!(clean);
+    assert!(sv.read().await.game_tasks.is_empty());
+    //the loaded chunk was saved, marked unloaded so the next start doesn't load it
+    assert!(storage.loaded_chunk_ids("world").await.is_empty());
+    assert_eq!(storage.chunk_ids("world").await, vec![chunk_id]);
+    assert_eq!(storage.entities_in_chunk("world", chunk_id).await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_refresh_token_reuse_revokes_session() {
+    let mut server = test_server().await;
+    assert!(server.create_user("alice", "password", false).await);
+    let (_, refresh_token) = server.generate_session("alice", "password").await.unwrap();
+    let session_id = refresh_token.split_once('.').unwrap().0.to_owned();
+    let (_, rotated) = server.refresh_session(&refresh_token).await.unwrap();
+    assert!(!server.is_session_revoked(&session_id));
+    //the old token was already exchanged, so whoever presents it again may have stolen it
+    assert!(server.refresh_session(&refresh_token).await.is_none());
+    assert!(server.is_session_revoked(&session_id));
+    assert!(server.refresh_session(&rotated).await.is_none());
+}
@@ -1,7 +1,10 @@
 use crossbeam_channel::{Receiver, Sender};
 use futures::{stream::SplitSink, SinkExt};
 use jsonwebtoken::{decode, DecodingKey, TokenData, Validation};
-use mmolib::{server_request_type::ServerRequestType, server_response_type::ServerResponseType};
+use mmolib::{
+    server_request_type::ServerRequestType,
+    server_response_type::{ServerMessage, ServerResponseType},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{fmt::Display, sync::Arc};
@@ -12,22 +15,25 @@ use tokio_tungstenite::{
 };
 use tracing::{info, span, Level};
 
-use crate::connection;
+use crate::connection::{self, ConnectionOptions, Outbound};
 
 #[derive(Debug)]
 pub struct ServerRequest {
     dat: ServerRequestType,
     world: Option<String>,
     session_token: Option<String>,
+    request_id: Option<u64>,
     claims: Option<TokenData<ServerClaims>>,
-    connnection_lock: Arc<RwLock<SplitSink<WebSocketStream<TcpStream>, Message>>>,
+    outbound: Outbound,
+    options: ConnectionOptions,
 }
 
 impl ServerRequest {
     pub fn new(
         dat: Value,
         secret_key: &str,
-        connection_lock: Arc<RwLock<SplitSink<WebSocketStream<TcpStream>, Message>>>,
+        outbound: Outbound,
+        options: ConnectionOptions,
     ) -> Result<ServerRequest, serde_json::Error> {
         let op: Option<String> = match dat.get("world_name") {
             Some(val) => val.as_str().map(Into::into),
@@ -41,6 +47,7 @@ impl ServerRequest {
             }
             None => None,
         };
+        let request_id = get_request_id(&dat);
         let claims = match &session_token {
             Some(s) => {
                 match decode::<ServerClaims>(
@@ -58,8 +65,10 @@ impl ServerRequest {
             dat: serde_json::from_value(dat)?,
             world: op,
             session_token: session_token,
+            request_id: request_id,
             claims: claims,
-            connnection_lock: connection_lock,
+            outbound: outbound,
+            options: options,
         })
     }
     pub fn get_user(&self) -> Option<&str> {
@@ -74,26 +83,61 @@ impl ServerRequest {
             None => false,
         }
     }
+    pub fn get_session_id(&self) -> Option<&str> {
+        match &self.claims {
+            Some(claims) => Some(&claims.claims.sid),
+            None => None,
+        }
+    }
     pub fn get_dat(&self) -> &ServerRequestType {
         &self.dat
     }
     pub fn get_world(&self) -> Option<&str> {
         self.world.as_deref()
     }
+    /**
+     * A connection sharing the socket's outbound queue, so pushes stay in order with replies.
+     */
     pub fn get_connection(&self) -> connection::Connection {
-        connection::Connection::new(self.connnection_lock.clone(), self.get_user().unwrap_or(""))
+        connection::Connection::new(
+            self.outbound.clone(),
+            self.options.clone(),
+            self.get_user().unwrap_or(""),
+        )
     }
-    pub async fn handle(self, request_dat: &ServerResponseType) {
-        let lk = self.connnection_lock.write();
-        let request_json = serde_json::to_string(request_dat).unwrap();
-        info!("Sent response: {}", request_json);
-        lk.await.send(Message::Text(request_json)).await;
+    pub fn set_options(&mut self, options: ConnectionOptions) {
+        self.options = options;
     }
+    pub fn get_request_id(&self) -> Option<u64> {
+        self.request_id
+    }
+    pub async fn handle(&self, request_dat: ServerResponseType) {
+        let message = connection::encode_message(
+            self.options.codec,
+            &ServerMessage::Reply {
+                request_id: self.request_id,
+                response: request_dat,
+            },
+        );
+        info!(
+            "Sent {:?} response of {} bytes",
+            self.options.codec,
+            message.len()
+        );
+        self.outbound.send(message).await;
+    }
+}
+/**
+ * The optional id a client puts next to the request type, echoed on the reply.
+ */
+pub fn get_request_id(dat: &Value) -> Option<u64> {
+    dat.get("request_id").and_then(Value::as_u64)
 }
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ServerClaims {
     pub user_name: String,
     pub is_admin: bool,
+    pub sid: String,
     pub exp: usize,
 }
 pub struct User {
@@ -0,0 +1,43 @@
+use tokio::signal;
+use tokio::sync::watch;
+
+/**
+ * Where the server is in shutting down. Draining worlds finish their tick and save, closing connections send their close frame.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum ShutdownPhase {
+    Running,
+    Draining,
+    Closing,
+}
+
+/**
+ * Resolves with the name of the first SIGINT or SIGTERM received.
+ */
+pub async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Could not listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        signal::ctrl_c().await;
+        "SIGINT"
+    }
+}
+
+/**
+ * Resolves once the server has reached the phase. Never resolves if the sender is dropped first.
+ */
+pub async fn wait_for_phase(rx: &mut watch::Receiver<ShutdownPhase>, phase: ShutdownPhase) {
+    while *rx.borrow() < phase {
+        if rx.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,784 @@
+use async_trait::async_trait;
+use mmolib::{
+    chunk::ChunkId, entity_id::EntityId, server_response_type::ErrorCode, world_rules::WorldRules,
+};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    ConnectOptions, Pool, QueryBuilder, Row, Sqlite, Transaction,
+};
+use tracing::info;
+
+use crate::migrations::{self, Migration, Step};
+use crate::storage::{ComponentRecord, SessionRecord, Storage, UserRecord, WorldSave};
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        steps: &[
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS worlds (
+            world_id TEXT PRIMARY KEY NOT NULL)",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS users (
+            user_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_name TEXT,
+            password_hash TEXT,
+            admin BOOLEAN)",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS chunks (
+            chunk_id INTEGER NOT NULL,
+            world_id TEXT NOT NULL REFERENCES worlds(world_id) ON DELETE CASCADE,
+            chunk_dat BLOB,
+            loaded BOOLEAN,
+            PRIMARY KEY (chunk_id, world_id))",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS entities (
+            entity_id INTEGER PRIMARY KEY,
+            chunk_id INTEGER,
+            world_id TEXT NOT NULL REFERENCES worlds(world_id) ON DELETE CASCADE)",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS components (
+            type_id TEXT NOT NULL,
+            entity_id INTEGER NOT NULL REFERENCES entities(entity_id) ON DELETE CASCADE,
+            dat TEXT,
+            PRIMARY KEY (entity_id, type_id))",
+            ),
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS players (
+            player_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER REFERENCES users(user_id) ON DELETE CASCADE,
+            entity_id INTEGER REFERENCES entities(entity_id) ON DELETE CASCADE)",
+            ),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "world rules",
+        steps: &[
+            Step::AddColumn {
+                table: "worlds",
+                column: "pvp",
+                definition: "BOOLEAN NOT NULL DEFAULT TRUE",
+            },
+            Step::AddColumn {
+                table: "worlds",
+                column: "keep_inventory",
+                definition: "BOOLEAN NOT NULL DEFAULT FALSE",
+            },
+            Step::AddColumn {
+                table: "worlds",
+                column: "spawn_x",
+                definition: "INTEGER NOT NULL DEFAULT 128",
+            },
+            Step::AddColumn {
+                table: "worlds",
+                column: "spawn_y",
+                definition: "INTEGER NOT NULL DEFAULT 128",
+            },
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "invite codes",
+        steps: &[
+            Step::AddColumn {
+                table: "users",
+                column: "invite_quota",
+                definition: "INTEGER",
+            },
+            Step::Sql(
+                r"CREATE TABLE IF NOT EXISTS invite_codes (
+            code TEXT PRIMARY KEY NOT NULL,
+            created_by INTEGER REFERENCES users(user_id) ON DELETE CASCADE,
+            uses_remaining INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL)",
+            ),
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "sessions",
+        steps: &[Step::Sql(
+            r"CREATE TABLE IF NOT EXISTS sessions (
+            session_id TEXT PRIMARY KEY NOT NULL,
+            user_id INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+            refresh_hash TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            revoked BOOLEAN NOT NULL DEFAULT FALSE)",
+        )],
+    },
+    Migration {
+        version: 5,
+        description: "unique user names",
+        steps: &[Step::CreateIndex {
+            table: "users",
+            name: "users_user_name",
+            columns: &["user_name"],
+            unique: true,
+        }],
+    },
+    Migration {
+        version: 6,
+        description: "index entities by chunk",
+        steps: &[Step::CreateIndex {
+            table: "entities",
+            name: "entities_world_chunk",
+            columns: &["world_id", "chunk_id"],
+            unique: false,
+        }],
+    },
+    Migration {
+        version: 7,
+        description: "banned users",
+        steps: &[Step::AddColumn {
+            table: "users",
+            column: "banned",
+            definition: "BOOLEAN NOT NULL DEFAULT FALSE",
+        }],
+    },
+];
+
+/**
+ * Rows per multi-row statement when saving a world, four binds per row stays under sqlite's default limit of 999 variables.
+ */
+const BATCH_SIZE: usize = 200;
+
+/**
+ * SQLite has no unsigned 64 bit integers, so ids and timestamps are stored as their i64 bit pattern.
+ */
+pub struct SqliteStorage {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStorage {
+    pub async fn connect(path: &str) -> Self {
+        info!("Opening sqlite database at {}", path);
+        let mut opts = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        opts.disable_statement_logging();
+        //a single connection serializes every query, which makes the read then write transactions below atomic
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(opts)
+            .await
+            .expect("Could not open sqlite database");
+        Self { pool: pool }
+    }
+}
+
+async fn count(tx: &mut Transaction<'_, Sqlite>, sql: &str, binds: &[&str]) -> i64 {
+    let mut query = sqlx::query(sql);
+    for bind in binds {
+        query = query.bind(*bind);
+    }
+    query
+        .fetch_one(&mut *tx)
+        .await
+        .expect("Could not inspect schema")
+        .get(0)
+}
+
+async fn apply_step(tx: &mut Transaction<'_, Sqlite>, step: &Step) {
+    let sql = match step {
+        Step::Sql(sql) => sql.to_string(),
+        Step::AddColumn {
+            table,
+            column,
+            definition,
+        } => {
+            let exists = count(
+                tx,
+                "SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?",
+                &[table, column],
+            )
+            .await;
+            if exists > 0 {
+                return;
+            }
+            migrations::add_column_sql(table, column, definition)
+        }
+        Step::CreateIndex {
+            table,
+            name,
+            columns,
+            unique,
+        } => {
+            let exists = count(
+                tx,
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name = ?",
+                &[name],
+            )
+            .await;
+            if exists > 0 {
+                return;
+            }
+            if *unique {
+                let duplicates =
+                    count(tx, &migrations::count_duplicates_sql(table, columns), &[]).await;
+                migrations::refuse_duplicates(table, name, columns, duplicates);
+            }
+            migrations::create_index_sql(table, name, columns, *unique)
+        }
+        //sqlite tables are created without the foreign keys that get dropped later
+        Step::DropForeignKey { .. } => return,
+    };
+    sqlx::query(&sql)
+        .execute(&mut *tx)
+        .await
+        .expect("Could not apply migration");
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) {
+        sqlx::query(
+            r"CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY NOT NULL,
+            description TEXT NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP)",
+        )
+        .execute(&self.pool)
+        .await
+        .expect("Could not create schema_version table");
+        let current_version: u32 =
+            sqlx::query("SELECT version FROM schema_version ORDER BY version DESC LIMIT 1")
+                .fetch_optional(&self.pool)
+                .await
+                .expect("Could not query schema version")
+                .map_or(0, |row| row.get("version"));
+        for migration in migrations::pending(MIGRATIONS, current_version) {
+            info!(
+                "Applying migration {}: {}",
+                migration.version, migration.description
+            );
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .expect("Could not begin transaction");
+            for step in migration.steps {
+                apply_step(&mut tx, step).await;
+            }
+            sqlx::query("INSERT INTO schema_version (version, description) VALUES (?,?)")
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&mut tx)
+                .await
+                .expect("Could not record schema version");
+            tx.commit().await.expect("Could not commit migration");
+        }
+    }
+
+    async fn create_world(&self, world_id: &str) -> bool {
+        sqlx::query("INSERT INTO worlds (world_id) VALUES (?)")
+            .bind(world_id)
+            .execute(&self.pool)
+            .await
+            .is_ok()
+    }
+    async fn world_exists(&self, world_id: &str) -> bool {
+        sqlx::query("SELECT world_id FROM worlds WHERE world_id = ?")
+            .bind(world_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+    async fn load_world_rules(&self, world_id: &str) -> Option<WorldRules> {
+        let row = sqlx::query(
+            "SELECT pvp, keep_inventory, spawn_x, spawn_y FROM worlds WHERE world_id = ?",
+        )
+        .bind(world_id)
+        .fetch_optional(&self.pool)
+        .await
+        .expect("error querying database for world rules")?;
+        let mut rules = WorldRules::new();
+        rules.pvp = row.try_get("pvp").expect("Could not get pvp");
+        rules.keep_inventory = row
+            .try_get("keep_inventory")
+            .expect("Could not get keep_inventory");
+        rules.spawn_point = (
+            row.try_get("spawn_x").expect("Could not get spawn_x"),
+            row.try_get("spawn_y").expect("Could not get spawn_y"),
+        );
+        Some(rules)
+    }
+    async fn save_world_rules(&self, world_id: &str, rules: &WorldRules) {
+        sqlx::query(
+            "UPDATE worlds SET pvp = ?, keep_inventory = ?, spawn_x = ?, spawn_y = ? WHERE world_id = ?",
+        )
+        .bind(rules.pvp)
+        .bind(rules.keep_inventory)
+        .bind(rules.spawn_point.0)
+        .bind(rules.spawn_point.1)
+        .bind(world_id)
+        .execute(&self.pool)
+        .await
+        .expect("Could not update world rules");
+    }
+
+    async fn chunk_exists(&self, world_id: &str, chunk_id: ChunkId) -> bool {
+        sqlx::query("SELECT chunk_id FROM chunks WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id() as i64)
+            .bind(world_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+            .is_some()
+    }
+    async fn load_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Option<Vec<u8>> {
+        let row = sqlx::query("SELECT chunk_dat FROM chunks WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id() as i64)
+            .bind(world_id)
+            .fetch_optional(&self.pool)
+            .await
+            .expect("error querying database for chunk")?;
+        sqlx::query("UPDATE chunks SET loaded = TRUE WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id() as i64)
+            .bind(world_id)
+            .execute(&self.pool)
+            .await
+            .expect("error updating chunk");
+        Some(
+            row.try_get("chunk_dat")
+                .expect("chunk format in database invalid"),
+        )
+    }
+    async fn loaded_chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        sqlx::query("SELECT chunk_id FROM chunks WHERE loaded = TRUE AND world_id = ?")
+            .bind(world_id)
+            .fetch_all(&self.pool)
+            .await
+            .expect("Error in database when loading chunks previously set as loaded")
+            .iter()
+            .map(|row| {
+                let id: i64 = row.try_get("chunk_id").expect("Could not get chunk_id");
+                ChunkId::new_raw(id as u64)
+            })
+            .collect()
+    }
+    async fn chunk_ids(&self, world_id: &str) -> Vec<ChunkId> {
+        sqlx::query("SELECT chunk_id FROM chunks WHERE world_id = ?")
+            .bind(world_id)
+            .fetch_all(&self.pool)
+            .await
+            .expect("Error in database when listing chunks")
+            .iter()
+            .map(|row| {
+                let id: i64 = row.try_get("chunk_id").expect("Could not get chunk_id");
+                ChunkId::new_raw(id as u64)
+            })
+            .collect()
+    }
+
+    async fn entities_in_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Vec<EntityId> {
+        sqlx::query("SELECT entity_id FROM entities WHERE chunk_id = ? AND world_id = ?")
+            .bind(chunk_id.id() as i64)
+            .bind(world_id)
+            .fetch_all(&self.pool)
+            .await
+            .expect("error querying database for entities")
+            .iter()
+            .map(|row| {
+                let id: i64 = row.try_get("entity_id").unwrap();
+                EntityId::new_with_number(id as u64)
+            })
+            .collect()
+    }
+    async fn load_components(&self, entity_id: EntityId) -> Vec<ComponentRecord> {
+        sqlx::query("SELECT type_id, dat FROM components WHERE entity_id = ?")
+            .bind(entity_id.id() as i64)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| ComponentRecord {
+                type_name: row.try_get("type_id").expect("Could not query type_id"),
+                dat: row.try_get("dat").unwrap(),
+            })
+            .collect()
+    }
+    async fn save_world(&self, world_id: &str, save: &WorldSave) {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .expect("Could not begin transaction");
+        for batch in save.chunks.chunks(BATCH_SIZE) {
+            QueryBuilder::new("INSERT INTO chunks (chunk_id, world_id, chunk_dat, loaded) ")
+                .push_values(batch, |mut row, chunk| {
+                    row.push_bind(chunk.chunk_id.id() as i64)
+                        .push_bind(world_id)
+                        .push_bind(&chunk.chunk_dat)
+                        .push_bind(chunk.loaded);
+                })
+                .push(" ON CONFLICT(chunk_id, world_id) DO UPDATE SET chunk_dat = excluded.chunk_dat, loaded = excluded.loaded")
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not save chunks");
+        }
+        for batch in save.entities.chunks(BATCH_SIZE) {
+            QueryBuilder::new("INSERT INTO entities (entity_id, chunk_id, world_id) ")
+                .push_values(batch, |mut row, entity| {
+                    row.push_bind(entity.entity_id.id() as i64)
+                        .push_bind(entity.chunk_id.map(|c| c.id() as i64))
+                        .push_bind(world_id);
+                })
+                .push(" ON CONFLICT(entity_id) DO UPDATE SET chunk_id = excluded.chunk_id")
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not save entities");
+        }
+        let components: Vec<(EntityId, &ComponentRecord)> = save
+            .entities
+            .iter()
+            .flat_map(|e| e.components.iter().map(move |c| (e.entity_id, c)))
+            .collect();
+        for batch in components.chunks(BATCH_SIZE) {
+            QueryBuilder::new("REPLACE INTO components (type_id, dat, entity_id) ")
+                .push_values(batch, |mut row, (entity_id, component)| {
+                    row.push_bind(&component.type_name)
+                        .push_bind(&component.dat)
+                        .push_bind(entity_id.id() as i64);
+                })
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not save components");
+        }
+        let removed: Vec<(EntityId, &String)> = save
+            .entities
+            .iter()
+            .flat_map(|e| e.removed_components.iter().map(move |c| (e.entity_id, c)))
+            .collect();
+        for batch in removed.chunks(BATCH_SIZE) {
+            QueryBuilder::new("DELETE FROM components WHERE (entity_id, type_id) IN ")
+                .push_tuples(batch, |mut row, (entity_id, type_name)| {
+                    row.push_bind(entity_id.id() as i64).push_bind(*type_name);
+                })
+                .build()
+                .execute(&mut tx)
+                .await
+                .expect("Could not delete removed components");
+        }
+        tx.commit().await.expect("Could not commit world save");
+    }
+    async fn delete_entity(&self, entity_id: EntityId) {
+        sqlx::query("DELETE FROM entities WHERE entity_id = ?")
+            .bind(entity_id.id() as i64)
+            .execute(&self.pool)
+            .await
+            .expect("Could not delete entity from table");
+    }
+
+    async fn find_player(&self, world_id: &str, username: &str) -> Option<EntityId> {
+        sqlx::query("SELECT entities.entity_id FROM players JOIN entities ON players.entity_id = entities.entity_id JOIN users ON players.user_id = users.user_id WHERE users.user_name = ? AND entities.world_id = ?")
+            .bind(username)
+            .bind(world_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+            .map(|row| {
+                let id: i64 = row.get("entity_id");
+                EntityId::new_with_number(id as u64)
+            })
+    }
+    async fn add_player_to_user(&self, username: &str, entity_id: EntityId) {
+        sqlx::query("INSERT INTO players (user_id, entity_id) VALUES ((SELECT user_id FROM users WHERE user_name = ?),?)")
+            .bind(username)
+            .bind(entity_id.id() as i64)
+            .execute(&self.pool)
+            .await
+            .expect("could not spawn player");
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str, is_admin: bool) -> bool {
+        sqlx::query("INSERT OR IGNORE INTO users (user_name, password_hash, admin) VALUES (?,?,?)")
+            .bind(username)
+            .bind(password_hash)
+            .bind(is_admin)
+            .execute(&self.pool)
+            .await
+            .unwrap()
+            .rows_affected()
+            > 0
+    }
+    async fn get_user(&self, username: &str) -> Option<UserRecord> {
+        sqlx::query(
+            "SELECT password_hash, admin, invite_quota, banned FROM users WHERE user_name = ?",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+        .unwrap()
+        .map(|row| UserRecord {
+            user_name: username.to_owned(),
+            password_hash: row.try_get("password_hash").unwrap(),
+            is_admin: row.try_get("admin").unwrap(),
+            invite_quota: row.try_get("invite_quota").unwrap(),
+            banned: row.try_get("banned").unwrap(),
+        })
+    }
+    async fn set_invite_quota(&self, username: &str, quota: u32) -> bool {
+        sqlx::query("UPDATE users SET invite_quota = ? WHERE user_name = ?")
+            .bind(quota)
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .unwrap()
+            .rows_affected()
+            > 0
+    }
+    async fn set_banned(&self, username: &str, banned: bool) -> bool {
+        sqlx::query("UPDATE users SET banned = ? WHERE user_name = ?")
+            .bind(banned)
+            .bind(username)
+            .execute(&self.pool)
+            .await
+            .unwrap()
+            .rows_affected()
+            > 0
+    }
+
+    async fn create_invite_code(
+        &self,
+        username: &str,
+        code: &str,
+        uses: u32,
+        expires_at: u64,
+        default_quota: Option<u32>,
+    ) -> Result<(), ErrorCode> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .expect("Could not begin transaction");
+        let row = sqlx::query("SELECT user_id, invite_quota FROM users WHERE user_name = ?")
+            .bind(username)
+            .fetch_optional(&mut tx)
+            .await
+            .unwrap();
+        let (user_id, quota): (i64, Option<u32>) = match row {
+            Some(r) => (
+                r.try_get("user_id").unwrap(),
+                r.try_get("invite_quota").unwrap(),
+            ),
+            None => return Err(ErrorCode::UserNotFound),
+        };
+        if let Some(default_quota) = default_quota {
+            let issued: i64 =
+                sqlx::query("SELECT COUNT(*) AS issued FROM invite_codes WHERE created_by = ?")
+                    .bind(user_id)
+                    .fetch_one(&mut tx)
+                    .await
+                    .unwrap()
+                    .try_get("issued")
+                    .unwrap();
+            if issued >= quota.unwrap_or(default_quota) as i64 {
+                return Err(ErrorCode::InviteQuotaReached);
+            }
+        }
+        sqlx::query(
+            "INSERT INTO invite_codes (code, created_by, uses_remaining, expires_at) VALUES (?,?,?,?)",
+        )
+        .bind(code)
+        .bind(user_id)
+        .bind(uses)
+        .bind(expires_at as i64)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+        tx.commit().await.expect("Could not commit invite code");
+        Ok(())
+    }
+    async fn register_user_with_invite_code(
+        &self,
+        username: &str,
+        password_hash: &str,
+        invite_code: &str,
+        now: u64,
+    ) -> Result<(), ErrorCode> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .expect("Could not begin transaction");
+        let row = sqlx::query("SELECT uses_remaining, expires_at FROM invite_codes WHERE code = ?")
+            .bind(invite_code)
+            .fetch_optional(&mut tx)
+            .await
+            .unwrap();
+        match row {
+            Some(r) => {
+                let uses_remaining: u32 = r.try_get("uses_remaining").unwrap();
+                let expires_at: i64 = r.try_get("expires_at").unwrap();
+                if uses_remaining == 0 || expires_at as u64 <= now {
+                    return Err(ErrorCode::InvalidInviteCode);
+                }
+            }
+            None => return Err(ErrorCode::InvalidInviteCode),
+        }
+        let inserted = sqlx::query(
+            "INSERT OR IGNORE INTO users (user_name, password_hash, admin) VALUES (?,?,?)",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(false)
+        .execute(&mut tx)
+        .await
+        .unwrap()
+        .rows_affected();
+        if inserted == 0 {
+            return Err(ErrorCode::AlreadyExists);
+        }
+        sqlx::query("UPDATE invite_codes SET uses_remaining = uses_remaining - 1 WHERE code = ?")
+            .bind(invite_code)
+            .execute(&mut tx)
+            .await
+            .unwrap();
+        tx.commit().await.expect("Could not commit registration");
+        Ok(())
+    }
+
+    async fn create_session(&self, session: &SessionRecord) {
+        sqlx::query(
+            "INSERT INTO sessions (session_id, user_id, refresh_hash, expires_at, revoked) VALUES (?,(SELECT user_id FROM users WHERE user_name = ?),?,?,?)",
+        )
+        .bind(&session.session_id)
+        .bind(&session.user_name)
+        .bind(&session.refresh_hash)
+        .bind(session.expires_at as i64)
+        .bind(session.revoked)
+        .execute(&self.pool)
+        .await
+        .expect("Could not create session");
+    }
+    async fn get_session(&self, session_id: &str) -> Option<SessionRecord> {
+        sqlx::query("SELECT sessions.refresh_hash, sessions.expires_at, sessions.revoked, users.user_name FROM sessions JOIN users ON sessions.user_id = users.user_id WHERE sessions.session_id = ?")
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap()
+            .map(|row| {
+                let expires_at: i64 = row.try_get("expires_at").unwrap();
+                SessionRecord {
+                    session_id: session_id.to_owned(),
+                    user_name: row.try_get("user_name").unwrap(),
+                    refresh_hash: row.try_get("refresh_hash").unwrap(),
+                    expires_at: expires_at as u64,
+                    revoked: row.try_get("revoked").unwrap(),
+                }
+            })
+    }
+    async fn rotate_session_refresh_hash(
+        &self,
+        session_id: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> bool {
+        sqlx::query("UPDATE sessions SET refresh_hash = ? WHERE session_id = ? AND refresh_hash = ? AND revoked = FALSE")
+            .bind(new_hash)
+            .bind(session_id)
+            .bind(old_hash)
+            .execute(&self.pool)
+            .await
+            .expect("Could not rotate refresh token")
+            .rows_affected()
+            == 1
+    }
+    async fn revoke_session(&self, session_id: &str) {
+        sqlx::query("UPDATE sessions SET revoked = TRUE WHERE session_id = ?")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .expect("Could not revoke session");
+    }
+    async fn active_session_ids(&self, username: &str) -> Vec<String> {
+        sqlx::query("SELECT sessions.session_id FROM sessions JOIN users ON sessions.user_id = users.user_id WHERE users.user_name = ? AND sessions.revoked = FALSE")
+            .bind(username)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.try_get("session_id").unwrap())
+            .collect()
+    }
+    async fn revoked_session_ids(&self, now: u64) -> Vec<String> {
+        sqlx::query("SELECT session_id FROM sessions WHERE revoked = TRUE AND expires_at > ?")
+            .bind(now as i64)
+            .fetch_all(&self.pool)
+            .await
+            .expect("Could not load revoked sessions")
+            .iter()
+            .map(|row| row.try_get("session_id").unwrap())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+async fn baseline_storage(name: &str) -> (SqliteStorage, std::path::PathBuf) {
+    let path = std::env::temp_dir().join(format!("mmoserv-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let storage = SqliteStorage::connect(path.to_str().unwrap()).await;
+    //the schema as it was before schema_version existed
+    for step in MIGRATIONS[0].steps {
+        if let Step::Sql(sql) = step {
+            sqlx::query(sql).execute(&storage.pool).await.unwrap();
+        }
+    }
+    sqlx::query("INSERT INTO worlds (world_id) VALUES ('world')")
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+    (storage, path)
+}
+
+#[tokio::test]
+async fn test_migrate_baseline_schema() {
+    let (storage, path) = baseline_storage("migrate").await;
+    sqlx::query(
+        "INSERT INTO users (user_name, password_hash, admin) VALUES ('alice', 'hash', FALSE)",
+    )
+    .execute(&storage.pool)
+    .await
+    .unwrap();
+    storage.migrate().await;
+    let user = storage.get_user("alice").await.unwrap();
+    assert_eq!(user.invite_quota, None);
+    assert!(!user.banned);
+    assert_eq!(
+        storage.load_world_rules("world").await.unwrap(),
+        WorldRules::new()
+    );
+    //running every step again changes nothing
+    sqlx::query("DELETE FROM schema_version")
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+    storage.migrate().await;
+    assert!(storage.get_user("alice").await.is_some());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+#[should_panic(expected = "users_user_name")]
+async fn test_migrate_refuses_duplicate_user_names() {
+    let (storage, _) = baseline_storage("duplicates").await;
+    for _ in 0..2 {
+        sqlx::query(
+            "INSERT INTO users (user_name, password_hash, admin) VALUES ('alice', 'hash', FALSE)",
+        )
+        .execute(&storage.pool)
+        .await
+        .unwrap();
+    }
+    storage.migrate().await;
+}
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use mmolib::{
+    chunk::ChunkId, entity_id::EntityId, server_response_type::ErrorCode, world_rules::WorldRules,
+};
+
+use crate::{args, memory_storage, mysql_storage, sqlite_storage};
+
+#[derive(Clone)]
+pub struct UserRecord {
+    pub user_name: String,
+    pub password_hash: String,
+    pub is_admin: bool,
+    pub invite_quota: Option<u32>,
+    pub banned: bool,
+}
+
+#[derive(Clone)]
+pub struct SessionRecord {
+    pub session_id: String,
+    pub user_name: String,
+    pub refresh_hash: String,
+    pub expires_at: u64,
+    pub revoked: bool,
+}
+
+#[derive(Clone)]
+pub struct ComponentRecord {
+    pub type_name: String,
+    pub dat: String,
+}
+
+#[derive(Clone)]
+pub struct EntityRecord {
+    pub entity_id: EntityId,
+    pub chunk_id: Option<ChunkId>,
+    pub components: Vec<ComponentRecord>,
+    pub removed_components: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct ChunkRecord {
+    pub chunk_id: ChunkId,
+    pub chunk_dat: Vec<u8>,
+    pub loaded: bool,
+}
+
+/**
+ * Everything written by one save of a world. Entities only carry the components that need writing.
+ */
+#[derive(Clone, Default)]
+pub struct WorldSave {
+    pub chunks: Vec<ChunkRecord>,
+    pub entities: Vec<EntityRecord>,
+}
+
+/**
+ * Everything the server persists. Implementations only move plain records around, turning records into entities happens in loaders.
+ */
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /**
+     * Brings the schema up to date, creating it if the database is empty.
+     */
+    async fn migrate(&self);
+
+    async fn create_world(&self, world_id: &str) -> bool;
+    async fn world_exists(&self, world_id: &str) -> bool;
+    async fn load_world_rules(&self, world_id: &str) -> Option<WorldRules>;
+    async fn save_world_rules(&self, world_id: &str, rules: &WorldRules);
+
+    async fn chunk_exists(&self, world_id: &str, chunk_id: ChunkId) -> bool;
+    /**
+     * Loads the serialized chunk and marks it as loaded.
+     */
+    async fn load_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Option<Vec<u8>>;
+    async fn loaded_chunk_ids(&self, world_id: &str) -> Vec<ChunkId>;
+    /**
+     * Every chunk stored for the world, loaded or not.
+     */
+    async fn chunk_ids(&self, world_id: &str) -> Vec<ChunkId>;
+
+    async fn entities_in_chunk(&self, world_id: &str, chunk_id: ChunkId) -> Vec<EntityId>;
+    async fn load_components(&self, entity_id: EntityId) -> Vec<ComponentRecord>;
+    /**
+     * Writes the chunks and entities of a save in a single transaction.
+     */
+    async fn save_world(&self, world_id: &str, save: &WorldSave);
+    async fn delete_entity(&self, entity_id: EntityId);
+
+    async fn find_player(&self, world_id: &str, username: &str) -> Option<EntityId>;
+    async fn add_player_to_user(&self, username: &str, entity_id: EntityId);
+
+    /**
+     * Returns false if the user already exists.
+     */
+    async fn create_user(&self, username: &str, password_hash: &str, is_admin: bool) -> bool;
+    async fn get_user(&self, username: &str) -> Option<UserRecord>;
+    async fn set_invite_quota(&self, username: &str, quota: u32) -> bool;
+    /**
+     * Returns false if the user doesn't exist. Banned users can't login or join a world.
+     */
+    async fn set_banned(&self, username: &str, banned: bool) -> bool;
+
+    /**
+     * Stores a new invite code created by a user. When a default quota is given, the user's own quota (or the default) is checked atomically with the insert.
+     */
+    async fn create_invite_code(
+        &self,
+        username: &str,
+        code: &str,
+        uses: u32,
+        expires_at: u64,
+        default_quota: Option<u32>,
+    ) -> Result<(), ErrorCode>;
+    /**
+     * Validates the invite code, creates the user and consumes one use of the code atomically.
+     */
+    async fn register_user_with_invite_code(
+        &self,
+        username: &str,
+        password_hash: &str,
+        invite_code: &str,
+        now: u64,
+    ) -> Result<(), ErrorCode>;
+
+    async fn create_session(&self, session: &SessionRecord);
+    async fn get_session(&self, session_id: &str) -> Option<SessionRecord>;
+    /**
+     * Replaces the refresh hash only if it is still old_hash and the session isn't revoked, so of two refreshes racing with the same token only one wins.
+     */
+    async fn rotate_session_refresh_hash(
+        &self,
+        session_id: &str,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> bool;
+    async fn revoke_session(&self, session_id: &str);
+    async fn active_session_ids(&self, username: &str) -> Vec<String>;
+    /**
+     * Revoked sessions whose refresh token hasn't expired yet.
+     */
+    async fn revoked_session_ids(&self, now: u64) -> Vec<String>;
+}
+
+pub async fn connect(args: &args::Args) -> Arc<dyn Storage> {
+    match args.storage {
+        args::StorageBackend::Mysql => Arc::new(mysql_storage::MySqlStorage::connect(args).await),
+        args::StorageBackend::Sqlite => {
+            Arc::new(sqlite_storage::SqliteStorage::connect(&args.sqlite_path).await)
+        }
+        args::StorageBackend::Memory => Arc::new(memory_storage::MemoryStorage::new()),
+    }
+}